@@ -0,0 +1,12 @@
+CREATE TABLE releases (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    url VARCHAR NOT NULL,
+    origin VARCHAR,
+    label VARCHAR,
+    suite VARCHAR,
+    codename VARCHAR,
+    date VARCHAR,
+    fetched_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX releases_url_idx ON releases(url);
@@ -0,0 +1 @@
+ALTER TABLE buildinfos DROP COLUMN signer;
@@ -0,0 +1 @@
+ALTER TABLE buildinfos ADD COLUMN signer VARCHAR;
@@ -0,0 +1,4 @@
+DROP INDEX artifacts_sha256_idx;
+
+ALTER TABLE artifacts DROP COLUMN size;
+ALTER TABLE artifacts DROP COLUMN sha256;
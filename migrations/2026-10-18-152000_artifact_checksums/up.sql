@@ -0,0 +1,4 @@
+ALTER TABLE artifacts ADD COLUMN sha256 VARCHAR;
+ALTER TABLE artifacts ADD COLUMN size BIGINT;
+
+CREATE INDEX artifacts_sha256_idx ON artifacts(sha256);
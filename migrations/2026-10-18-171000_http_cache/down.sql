@@ -0,0 +1 @@
+DROP TABLE http_cache;
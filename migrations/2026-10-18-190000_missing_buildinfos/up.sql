@@ -0,0 +1,9 @@
+CREATE TABLE missing_buildinfos (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    deb_folder VARCHAR NOT NULL,
+    file_name VARCHAR NOT NULL,
+    listing_hash VARCHAR NOT NULL,
+    checked_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX missing_buildinfos_pkg_idx ON missing_buildinfos(deb_folder, file_name);
@@ -0,0 +1,10 @@
+CREATE TABLE parse_failures (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    url VARCHAR NOT NULL,
+    kind VARCHAR NOT NULL,
+    line INTEGER,
+    message VARCHAR NOT NULL,
+    failed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX parse_failures_url_idx ON parse_failures(url);
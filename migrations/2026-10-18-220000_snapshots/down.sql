@@ -0,0 +1,2 @@
+DROP TABLE snapshot_indexes;
+DROP TABLE snapshots;
@@ -0,0 +1,14 @@
+CREATE TABLE snapshots (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE snapshot_indexes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    snapshot_id INTEGER NOT NULL,
+    url VARCHAR NOT NULL,
+    body BLOB NOT NULL,
+    FOREIGN KEY(snapshot_id) REFERENCES snapshots(id) ON DELETE CASCADE
+);
+
+CREATE INDEX snapshot_indexes_snapshot_id_idx ON snapshot_indexes(snapshot_id);
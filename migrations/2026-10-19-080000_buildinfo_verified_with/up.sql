@@ -0,0 +1 @@
+ALTER TABLE buildinfos ADD COLUMN verified_with VARCHAR;
@@ -0,0 +1,11 @@
+DROP TABLE http_cache;
+
+CREATE TABLE http_cache (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    url VARCHAR NOT NULL,
+    etag VARCHAR,
+    last_modified VARCHAR,
+    body BLOB NOT NULL
+);
+
+CREATE UNIQUE INDEX http_cache_url_idx ON http_cache(url);
@@ -0,0 +1,12 @@
+DROP TABLE snapshot_indexes;
+DELETE FROM snapshots;
+
+CREATE TABLE snapshot_indexes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    snapshot_id INTEGER NOT NULL,
+    url VARCHAR NOT NULL,
+    body BLOB NOT NULL,
+    FOREIGN KEY(snapshot_id) REFERENCES snapshots(id) ON DELETE CASCADE
+);
+
+CREATE INDEX snapshot_indexes_snapshot_id_idx ON snapshot_indexes(snapshot_id);
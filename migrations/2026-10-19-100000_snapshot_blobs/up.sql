@@ -0,0 +1,13 @@
+-- snapshots refer to the Packages indexes in the blob store now, the old copies can't be moved there
+DROP TABLE snapshot_indexes;
+DELETE FROM snapshots;
+
+CREATE TABLE snapshot_indexes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    snapshot_id INTEGER NOT NULL,
+    url VARCHAR NOT NULL,
+    blob VARCHAR NOT NULL,
+    FOREIGN KEY(snapshot_id) REFERENCES snapshots(id) ON DELETE CASCADE
+);
+
+CREATE INDEX snapshot_indexes_snapshot_id_idx ON snapshot_indexes(snapshot_id);
@@ -0,0 +1,2 @@
+ALTER TABLE parse_failures DROP COLUMN listing_size;
+ALTER TABLE parse_failures DROP COLUMN listing_mtime;
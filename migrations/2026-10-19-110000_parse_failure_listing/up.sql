@@ -0,0 +1,3 @@
+-- the directory listing entry of the file, it is downloaded again once it changes
+ALTER TABLE parse_failures ADD COLUMN listing_mtime TIMESTAMP;
+ALTER TABLE parse_failures ADD COLUMN listing_size BIGINT;
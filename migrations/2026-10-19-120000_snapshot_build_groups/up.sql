@@ -0,0 +1,2 @@
+-- the build groups that were generated from the snapshot, as json in the blob store
+ALTER TABLE snapshots ADD COLUMN build_groups VARCHAR;
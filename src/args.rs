@@ -1,21 +1,264 @@
+use rebuilderd_common::auth;
+use rebuilderd_debian_buildinfo_crawler::crawler;
+use rebuilderd_debian_buildinfo_crawler::errors::*;
+use std::str::FromStr;
+use std::time::Duration;
+
 #[derive(Debug, clap::Parser)]
 pub struct Args {
     #[clap(long = "db")]
     pub database: String,
-    #[clap(long)]
-    pub packages_db: String,
-    #[clap(short = 'v', parse(from_occurrences))]
+    #[clap(short = 'v', global = true, parse(from_occurrences))]
     pub verbose: u8,
+    #[clap(subcommand)]
+    pub subcommand: SubCommand,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum SubCommand {
+    /// Find the buildinfo files of all packages and store them in the database
+    Crawl(Crawl),
+    /// Generate the json for rebuilderd from the database, without crawling buildinfo files
+    Export(Export),
+    /// Show which buildinfo file matches a .deb and why
+    Lookup(Lookup),
+    /// Show the size of the cache and how many packages are missing buildinfo files
+    Stats,
+    /// Remove entries of packages that weren't seen in a while and compact the database
+    Gc(Gc),
+    /// Show the changes between two sets of build groups, e.g. from two nightly imports
+    Diff(Diff),
+}
+
+/// Where to find the Packages indexes
+#[derive(Debug, clap::Args)]
+pub struct Index {
+    /// Use these Packages indexes instead of looking them up in the Release files of --source
+    #[clap(long = "packages-db")]
+    pub packages_dbs: Vec<String>,
     #[clap(long)]
     pub source: String,
-    #[clap(long)]
-    pub distro: String,
+    /// The component to import, e.g. `main` or `contrib`
     #[clap(long)]
     pub suite: String,
-    #[clap(long)]
-    pub skip_crawl: bool,
     #[clap(long = "release")]
     pub releases: Vec<String>,
     #[clap(long = "arch")]
     pub architectures: Vec<String>,
+    /// Verify InRelease and buildinfo signatures with this keyring (.gpg or .asc)
+    #[clap(long)]
+    pub keyring: Option<String>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Http {
+    /// How often a failed http request or download is retried
+    #[clap(long, default_value = "5")]
+    pub retries: u32,
+    /// The initial delay before retrying, doubled after every attempt
+    #[clap(long, default_value = "1s", parse(try_from_str = humantime::parse_duration))]
+    pub retry_backoff: Duration,
+    /// The longest delay before retrying, also caps the Retry-After header of the server
+    #[clap(long, default_value = "5m", parse(try_from_str = humantime::parse_duration))]
+    pub max_retry_delay: Duration,
+    /// Maximum number of http requests per second, 0 means unlimited
+    #[clap(long, default_value = "0")]
+    pub rate_limit: f64,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Crawl {
+    #[clap(flatten)]
+    pub index: Index,
+    #[clap(flatten)]
+    pub http: Http,
+    /// Crawl packages without buildinfo again after this time, even if the directory listing didn't change
+    #[clap(long, default_value = "7d", parse(try_from_str = humantime::parse_duration))]
+    pub recheck_after: Duration,
+    /// Fail the run if a buildinfo file can't be parsed, instead of skipping it
+    #[clap(long)]
+    pub strict: bool,
+    /// The public url of the buildinfo files, it's stored in the database and used as `input_url` of the build groups
+    #[clap(long, default_value = "https://buildinfos.debian.net/", parse(try_from_str = parse_public_url))]
+    pub buildinfo_base: String,
+    /// Read the buildinfo files from this local directory or http mirror instead, it needs the same `buildinfo-pool/` layout
+    #[clap(long)]
+    pub buildinfo_mirror: Option<String>,
+    /// How many source packages to crawl in parallel
+    #[clap(long, default_value = "1")]
+    pub concurrency: usize,
+    /// Write a json report of failed listings, downloads, parse errors and packages without buildinfo to this file
+    #[clap(long)]
+    pub report: Option<String>,
+}
+
+impl Crawl {
+    pub fn crawler_config(&self) -> crawler::Config {
+        crawler::Config {
+            source: self.index.source.clone(),
+            // no build groups are generated while crawling
+            distro: String::new(),
+            suite: self.index.suite.clone(),
+            buildinfo_base: self.buildinfo_base.clone(),
+            buildinfo_mirror: self.buildinfo_mirror.clone(),
+            skip_crawl: false,
+            recheck_after: self.recheck_after,
+            strict: self.strict,
+            exclude_unsigned: false,
+            concurrency: self.concurrency,
+        }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Export {
+    #[clap(flatten)]
+    pub index: Index,
+    #[clap(flatten)]
+    pub http: Http,
+    #[clap(long)]
+    pub distro: String,
+    /// Read the Packages indexes from this snapshot of an earlier crawl, either `latest` (the default) or the id of the snapshot
+    #[clap(long, conflicts_with_all = &["packages-dbs", "releases", "architectures", "fetch"])]
+    pub snapshot: Option<SnapshotId>,
+    /// Download the Packages indexes given with --packages-db or --release and --arch instead of using a snapshot
+    #[clap(long)]
+    pub fetch: bool,
+    /// Used for the `missing-buildinfo` urls of packages without buildinfo file
+    #[clap(long, default_value = "https://buildinfos.debian.net/", parse(try_from_str = parse_public_url))]
+    pub buildinfo_base: String,
+    /// Don't use buildinfo files without a valid signature, requires --keyring
+    #[clap(long, requires = "keyring")]
+    pub exclude_unsigned: bool,
+    /// Write a json report of packages without buildinfo to this file
+    #[clap(long)]
+    pub report: Option<String>,
+    /// Submit the build groups to this rebuilderd instance instead of printing them
+    #[clap(long)]
+    pub rebuilderd_url: Option<String>,
+    /// The auth cookie for rebuilderd. Like rebuildctl, it's read from REBUILDERD_COOKIE_PATH or the cookie of this endpoint in rebuilderd.conf if not given
+    #[clap(long, requires = "rebuilderd-url")]
+    pub auth_cookie: Option<String>,
+    /// Read the auth cookie for rebuilderd from this file
+    #[clap(long, requires = "rebuilderd-url", conflicts_with = "auth-cookie")]
+    pub auth_cookie_file: Option<String>,
+    /// Print the request for rebuilderd instead of submitting it
+    #[clap(long, requires = "rebuilderd-url")]
+    pub dry_run: bool,
+}
+
+impl Export {
+    /// The auth cookie given on the command line, `None` if rebuilderd-common should look it up
+    pub fn auth_cookie(&self) -> Result<Option<String>> {
+        if let Some(cookie) = &self.auth_cookie {
+            Ok(Some(cookie.clone()))
+        } else if let Some(path) = &self.auth_cookie_file {
+            let cookie = auth::read_cookie_from_file(path)
+                .with_context(|| anyhow!("Failed to read auth cookie from {:?}", path))?;
+            Ok(Some(cookie))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn crawler_config(&self) -> crawler::Config {
+        crawler::Config {
+            source: self.index.source.clone(),
+            distro: self.distro.clone(),
+            suite: self.index.suite.clone(),
+            buildinfo_base: self.buildinfo_base.clone(),
+            buildinfo_mirror: None,
+            skip_crawl: true,
+            recheck_after: Duration::default(),
+            strict: false,
+            exclude_unsigned: self.exclude_unsigned,
+            concurrency: 1,
+        }
+    }
+}
+
+// the urls end up in the build groups, rebuilderd workers can't read from our filesystem
+fn parse_public_url(s: &str) -> Result<String> {
+    if !s.starts_with("http://") && !s.starts_with("https://") {
+        bail!("Expected a http url, use --buildinfo-mirror to read from a local directory");
+    }
+    Ok(s.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SnapshotId {
+    Latest,
+    Id(i32),
+}
+
+impl SnapshotId {
+    pub fn id(self) -> Option<i32> {
+        match self {
+            SnapshotId::Latest => None,
+            SnapshotId::Id(id) => Some(id),
+        }
+    }
+}
+
+impl FromStr for SnapshotId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SnapshotId> {
+        if s == "latest" {
+            Ok(SnapshotId::Latest)
+        } else {
+            let id = s
+                .parse()
+                .with_context(|| anyhow!("Invalid snapshot id: {:?}", s))?;
+            Ok(SnapshotId::Id(id))
+        }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Lookup {
+    /// The .deb to look up, if the file doesn't exist only the filename is used
+    pub file: String,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Gc {
+    /// Remove packages without buildinfo and parse failures that weren't seen for this long
+    #[clap(long, default_value = "30d", parse(try_from_str = humantime::parse_duration))]
+    pub older_than: Duration,
+    /// How many snapshots of Packages indexes are kept
+    #[clap(long, default_value = "7")]
+    pub keep_snapshots: i64,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Diff {
+    /// The older build groups, a json file or with --snapshots the id of a snapshot
+    pub old: String,
+    /// The newer build groups, a json file or with --snapshots the id of a snapshot
+    pub new: String,
+    /// Compare the build groups that were generated by the crawls of two snapshots instead
+    #[clap(long)]
+    pub snapshots: bool,
+    /// Print the changes as json
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::IntoApp;
+
+    #[test]
+    fn verify_args() {
+        Args::into_app().debug_assert();
+    }
+
+    #[test]
+    fn parse_snapshot_id() {
+        assert_eq!("latest".parse::<SnapshotId>().unwrap(), SnapshotId::Latest);
+        assert_eq!("3".parse::<SnapshotId>().unwrap(), SnapshotId::Id(3));
+        assert!("yesterday".parse::<SnapshotId>().is_err());
+    }
 }
@@ -0,0 +1,203 @@
+use crate::errors::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tempfile::{NamedTempFile, TempDir};
+
+/// Files that are too large for the database, stored on disk and addressed by their sha256
+pub struct BlobStore {
+    dir: PathBuf,
+    // in-memory databases get a temporary directory that is removed on drop
+    _temp: Option<TempDir>,
+}
+
+/// A file in the blob store
+#[derive(Debug, Clone, PartialEq)]
+pub struct Blob {
+    pub sha256: String,
+    pub size: u64,
+}
+
+fn is_sha256(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+impl BlobStore {
+    pub fn new(dir: PathBuf) -> Result<BlobStore> {
+        fs::create_dir_all(&dir)
+            .with_context(|| anyhow!("Failed to create blob directory: {:?}", dir))?;
+        Ok(BlobStore { dir, _temp: None })
+    }
+
+    pub fn temporary() -> Result<BlobStore> {
+        let temp = tempfile::tempdir().context("Failed to create temporary blob directory")?;
+        Ok(BlobStore {
+            dir: temp.path().to_path_buf(),
+            _temp: Some(temp),
+        })
+    }
+
+    fn path(&self, sha256: &str) -> Result<PathBuf> {
+        if !is_sha256(sha256) {
+            bail!("Invalid blob id: {:?}", sha256);
+        }
+        Ok(self.dir.join(&sha256[..2]).join(sha256))
+    }
+
+    /// Start writing a new blob, it's added to the store with `BlobWriter::commit`
+    pub fn writer(&self) -> Result<BlobWriter<'_>> {
+        let file = NamedTempFile::new_in(&self.dir).context("Failed to create blob file")?;
+        Ok(BlobWriter {
+            store: self,
+            file,
+            hasher: Sha256::new(),
+            size: 0,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn add(&self, data: &[u8]) -> Result<Blob> {
+        let mut writer = self.writer()?;
+        writer.write_all(data)?;
+        writer.commit()
+    }
+
+    pub fn open(&self, sha256: &str) -> Result<File> {
+        let path = self.path(sha256)?;
+        File::open(&path).with_context(|| anyhow!("Failed to open blob: {:?}", path))
+    }
+
+    /// Get the size of a blob, `None` if it doesn't exist
+    pub fn stat(&self, sha256: &str) -> Result<Option<Blob>> {
+        match fs::metadata(self.path(sha256)?) {
+            Ok(metadata) => Ok(Some(Blob {
+                sha256: sha256.to_string(),
+                size: metadata.len(),
+            })),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn files(&self) -> Result<Vec<(String, fs::Metadata)>> {
+        let mut files = Vec::new();
+        for dir in fs::read_dir(&self.dir)? {
+            let dir = dir?;
+            if !dir.file_type()?.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(dir.path())? {
+                let file = file?;
+                if let Some(name) = file.file_name().to_str().filter(|name| is_sha256(name)) {
+                    files.push((name.to_string(), file.metadata()?));
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Number of blobs and their total size in bytes
+    pub fn usage(&self) -> Result<(u64, u64)> {
+        let files = self.files()?;
+        let bytes = files.iter().map(|(_, metadata)| metadata.len()).sum();
+        Ok((files.len() as u64, bytes))
+    }
+
+    /// Delete all blobs that are not in `keep` and were written before `before`, files that were
+    /// just written may not be referenced by the database yet
+    pub fn retain(&self, keep: &HashSet<String>, before: SystemTime) -> Result<usize> {
+        let mut removed = 0;
+        for (sha256, metadata) in self.files()? {
+            if keep.contains(&sha256) || metadata.modified()? >= before {
+                continue;
+            }
+            debug!("Removing unreferenced blob {:?}", sha256);
+            fs::remove_file(self.path(&sha256)?)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+}
+
+/// Hashes everything that is written, the file is moved into the store on commit
+pub struct BlobWriter<'a> {
+    store: &'a BlobStore,
+    file: NamedTempFile,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl Write for BlobWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl BlobWriter<'_> {
+    pub fn commit(mut self) -> Result<Blob> {
+        self.file.flush()?;
+        let sha256 = format!("{:x}", self.hasher.finalize());
+        let path = self.store.path(&sha256)?;
+        if !path.exists() {
+            fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+            self.file
+                .persist(&path)
+                .with_context(|| anyhow!("Failed to store blob: {:?}", path))?;
+        }
+        Ok(Blob {
+            sha256,
+            size: self.size,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn add_and_remove_blobs() {
+        let store = BlobStore::temporary().unwrap();
+        let blob = store.add(b"test").unwrap();
+        assert_eq!(
+            blob,
+            Blob {
+                sha256: "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+                    .to_string(),
+                size: 4,
+            }
+        );
+        // identical content is only stored once
+        assert_eq!(store.add(b"test").unwrap(), blob);
+        let other = store.add(b"other").unwrap();
+        assert_eq!(store.usage().unwrap(), (2, 9));
+
+        let mut body = String::new();
+        store
+            .open(&blob.sha256)
+            .unwrap()
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "test");
+        assert!(store.open("../../etc/passwd").is_err());
+
+        let keep = HashSet::from([blob.sha256.clone()]);
+        assert_eq!(store.retain(&keep, SystemTime::UNIX_EPOCH).unwrap(), 0);
+        let later = SystemTime::now() + Duration::from_secs(60);
+        assert_eq!(store.retain(&keep, later).unwrap(), 1);
+        assert_eq!(store.stat(&other.sha256).unwrap(), None);
+        assert_eq!(store.stat(&blob.sha256).unwrap(), Some(blob));
+    }
+}
@@ -1,79 +1,653 @@
-use crate::errors::*;
-use std::collections::HashSet;
+use crate::pgp;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
 use std::str::FromStr;
 
+/// A parsed .buildinfo file, see `man 5 deb-buildinfo`
 #[derive(Debug, PartialEq)]
 pub struct Buildinfo {
+    /// The version of the buildinfo format, usually `1.0`
+    pub format: Option<String>,
+    /// The name of the source package
     pub source: String,
+    /// The version from `Source: name (version)`, only present if it differs from `version`
+    pub source_version: Option<String>,
+    /// The binary packages that were built
+    pub binary: Vec<String>,
+    /// The architectures that were built, e.g. `amd64 source` or `all`
     pub architecture: String,
+    /// The version of the binary packages, including a binNMU suffix
     pub version: String,
+    /// The changelog entry of a binNMU, continuation lines without their leading space
+    pub binary_only_changes: Option<String>,
+    /// The file names of all artifacts listed in any of the checksum fields
     pub artifacts: HashSet<String>,
+    /// The `Checksums-Md5` field
+    pub checksums_md5: Vec<ArtifactChecksum>,
+    /// The `Checksums-Sha1` field
+    pub checksums_sha1: Vec<ArtifactChecksum>,
+    /// The `Checksums-Sha256` field
+    pub checksums_sha256: Vec<ArtifactChecksum>,
+    /// The distribution the package was built for, e.g. `Debian`
+    pub build_origin: Option<String>,
+    /// The architecture of the build machine
+    pub build_architecture: Option<String>,
+    /// When the build finished, in RFC 2822 format
+    pub build_date: Option<String>,
+    /// The directory the package was built in
+    pub build_path: Option<String>,
+    /// Reasons why the build environment may be unclean, e.g. `merged-usr-via-aliased-dirs`
+    pub build_tainted_by: Vec<String>,
+    /// The packages that were installed during the build
+    pub installed_build_depends: Vec<InstalledPackage>,
+    /// The environment variables of the build, with their quotes removed
+    pub environment: BTreeMap<String, String>,
+    /// Fields that aren't parsed, in their original order. Continuation lines are separated by
+    /// `\n` and kept without their leading space
+    pub other_fields: Vec<(String, String)>,
+    /// The names of all fields in the order they were read, the fields are written back in this
+    /// order. Known fields that aren't listed are placed at their position in the dpkg order
+    pub field_order: Vec<String>,
+}
+
+/// A line of a `Checksums-*` field
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArtifactChecksum {
+    /// The hash in hex
+    pub hash: String,
+    /// The size in bytes
+    pub size: u64,
+    /// The file name of the artifact
+    pub file_name: String,
+}
+
+/// An entry of `Installed-Build-Depends`, e.g. `libc6 (= 2.33-1)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstalledPackage {
+    /// The package name, may contain an architecture qualifier like `libc6:amd64`
+    pub name: String,
+    /// The exact version that was installed
+    pub version: String,
+}
+
+impl Buildinfo {
+    /// The sha256 checksum of an artifact, if the buildinfo lists it
+    pub fn sha256(&self, file_name: &str) -> Option<&ArtifactChecksum> {
+        self.checksums_sha256
+            .iter()
+            .find(|c| c.file_name == file_name)
+    }
+}
+
+/// Why a buildinfo file couldn't be parsed, line numbers start at 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The file isn't valid utf8
+    NotUtf8 {
+        /// The line of the first invalid byte
+        line: usize,
+    },
+    /// The OpenPGP signature around the file is malformed
+    InvalidArmor {
+        /// The line the armor was rejected at
+        line: usize,
+        /// What is wrong with the armor
+        reason: &'static str,
+    },
+    /// A line is neither a field nor a continuation line
+    InvalidLine {
+        /// The invalid line
+        line: usize,
+    },
+    /// A field shows up more than once
+    DuplicateField {
+        /// The line of the second occurrence
+        line: usize,
+        /// The name of the field
+        field: String,
+    },
+    /// A field that every buildinfo file needs is missing
+    MissingField {
+        /// The name of the field
+        field: &'static str,
+    },
+    /// A line of a `Checksums-*` field isn't `<hash> <size> <filename>`
+    InvalidChecksum {
+        /// The invalid line
+        line: usize,
+    },
+    /// A field has a value that can't be parsed
+    InvalidValue {
+        /// The line with the invalid value
+        line: usize,
+        /// The name of the field
+        field: String,
+    },
+}
+
+impl ParseError {
+    /// A short identifier of the kind of error
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ParseError::NotUtf8 { .. } => "not-utf8",
+            ParseError::InvalidArmor { .. } => "invalid-armor",
+            ParseError::InvalidLine { .. } => "invalid-line",
+            ParseError::DuplicateField { .. } => "duplicate-field",
+            ParseError::MissingField { .. } => "missing-field",
+            ParseError::InvalidChecksum { .. } => "invalid-checksum",
+            ParseError::InvalidValue { .. } => "invalid-value",
+        }
+    }
+
+    /// The line the error was found at, `None` if it's about the whole file
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            ParseError::NotUtf8 { line }
+            | ParseError::InvalidArmor { line, .. }
+            | ParseError::InvalidLine { line }
+            | ParseError::DuplicateField { line, .. }
+            | ParseError::InvalidChecksum { line }
+            | ParseError::InvalidValue { line, .. } => Some(*line),
+            ParseError::MissingField { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::NotUtf8 { line } => write!(f, "Line {}: invalid utf8", line),
+            ParseError::InvalidArmor { line, reason } => {
+                write!(f, "Line {}: invalid signature armor, {}", line, reason)
+            }
+            ParseError::InvalidLine { line } => write!(f, "Line {}: expected a field", line),
+            ParseError::DuplicateField { line, field } => {
+                write!(f, "Line {}: duplicate field `{}`", line, field)
+            }
+            ParseError::MissingField { field } => write!(f, "Missing `{}` field", field),
+            ParseError::InvalidChecksum { line } => write!(f, "Line {}: invalid checksum", line),
+            ParseError::InvalidValue { line, field } => {
+                write!(f, "Line {}: invalid value in `{}`", line, field)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// The lines of the signed message with their line number, the signature itself is not verified here
+fn message_lines(s: &str) -> std::result::Result<Vec<(usize, &str)>, ParseError> {
+    let mut lines = s.lines().enumerate().map(|(i, line)| (i + 1, line));
+    let armor_error = |line, reason| ParseError::InvalidArmor { line, reason };
+
+    match lines.next() {
+        Some((_, pgp::SIGNED_MESSAGE_HEADER)) => (),
+        Some(first) => return Ok(std::iter::once(first).chain(lines).collect()),
+        None => return Ok(Vec::new()),
+    }
+
+    // skip the armor headers until the first empty line
+    let mut last = 1;
+    loop {
+        match lines.next() {
+            Some((_, "")) => break,
+            Some((num, _)) => last = num,
+            None => return Err(armor_error(last, "missing end of armor headers")),
+        }
+    }
+
+    let mut out = Vec::new();
+    loop {
+        match lines.next() {
+            Some((num, pgp::SIGNATURE_HEADER)) => {
+                last = num;
+                break;
+            }
+            Some((num, line)) => {
+                last = num;
+                out.push((num, line.strip_prefix("- ").unwrap_or(line)));
+            }
+            None => return Err(armor_error(last, "missing signature")),
+        }
+    }
+
+    for (num, line) in lines {
+        if line == pgp::SIGNATURE_FOOTER {
+            return Ok(out);
+        }
+        last = num;
+    }
+    Err(armor_error(last, "missing end of signature"))
+}
+
+// A field of the deb822 paragraph, continuation lines are kept without their leading space
+struct Field<'a> {
+    name: &'a str,
+    value: &'a str,
+    line: usize,
+    lines: Vec<(usize, &'a str)>,
+}
+
+impl<'a> Field<'a> {
+    // all lines of the field, including the first one
+    fn all_lines(&self) -> impl Iterator<Item = (usize, &'a str)> + '_ {
+        std::iter::once((self.line, self.value)).chain(self.lines.iter().copied())
+    }
+
+    // all words of the field, no matter if they're on the first line or continuation lines
+    fn words(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.all_lines()
+            .flat_map(|(_, line)| line.split_whitespace())
+    }
+
+    // the value with its continuation lines, as it's kept for fields that aren't parsed
+    fn raw_value(&self) -> String {
+        self.all_lines()
+            .map(|(_, line)| line)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn invalid_value(&self, line: usize) -> ParseError {
+        ParseError::InvalidValue {
+            line,
+            field: self.name.to_string(),
+        }
+    }
+}
+
+fn parse_fields(s: &str) -> std::result::Result<Vec<Field<'_>>, ParseError> {
+    let mut fields = Vec::<Field>::new();
+    let mut seen = HashSet::new();
+    for (num, line) in message_lines(s)? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(line) = line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')) {
+            let field = fields
+                .last_mut()
+                .ok_or(ParseError::InvalidLine { line: num })?;
+            field.lines.push((num, line));
+        } else {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(ParseError::InvalidLine { line: num })?;
+            if !seen.insert(name) {
+                return Err(ParseError::DuplicateField {
+                    line: num,
+                    field: name.to_string(),
+                });
+            }
+            fields.push(Field {
+                name,
+                value: value.trim(),
+                line: num,
+                lines: Vec::new(),
+            });
+        }
+    }
+    Ok(fields)
+}
+
+impl Buildinfo {
+    /// Parse a downloaded buildinfo file, which isn't necessarily utf8
+    pub fn from_bytes(bytes: &[u8]) -> std::result::Result<Buildinfo, ParseError> {
+        match std::str::from_utf8(bytes) {
+            Ok(s) => s.parse(),
+            Err(err) => {
+                let valid = &bytes[..err.valid_up_to()];
+                let line = valid.iter().filter(|b| **b == b'\n').count() + 1;
+                Err(ParseError::NotUtf8 { line })
+            }
+        }
+    }
 }
 
 impl FromStr for Buildinfo {
-    type Err = Error;
+    type Err = ParseError;
 
-    fn from_str(s: &str) -> Result<Self> {
+    fn from_str(s: &str) -> std::result::Result<Self, ParseError> {
+        let mut format = None;
         let mut source = None;
+        let mut source_version = None;
+        let mut binary = Vec::new();
         let mut architecture = None;
         let mut version = None;
+        let mut binary_only_changes = None;
         let mut artifacts = HashSet::new();
+        let mut checksums_md5 = Vec::new();
+        let mut checksums_sha1 = Vec::new();
+        let mut checksums_sha256 = Vec::new();
+        let mut build_origin = None;
+        let mut build_architecture = None;
+        let mut build_date = None;
+        let mut build_path = None;
+        let mut build_tainted_by = Vec::new();
+        let mut installed_build_depends = Vec::new();
+        let mut environment = BTreeMap::new();
+        let mut other_fields = Vec::new();
+        let mut field_order = Vec::new();
 
-        let mut section = None;
-        for line in s.split('\n') {
-            // Set section
-            if let Some(s) = line.strip_suffix(':') {
-                section = Some(s.to_string());
-            }
-
-            if line.starts_with(' ') {
-                // we're inside of a section
-                if let Some(section) = &section {
-                    if section.starts_with("Checksums-") {
-                        if let Some(artifact) = artifact_from_checksum_line(line) {
-                            artifacts.insert(artifact);
+        for field in parse_fields(s)? {
+            field_order.push(field.name.to_string());
+            let value = || Some(field.value.to_string());
+            match field.name {
+                "Format" => format = value(),
+                "Source" => {
+                    let (src, ver) = match field.value.split_once(' ') {
+                        Some((src, ver)) => {
+                            let ver = ver.trim().trim_start_matches('(').trim_end_matches(')');
+                            (src, Some(ver.to_string()))
                         }
+                        None => (field.value, None),
+                    };
+                    source = Some(src.to_string());
+                    source_version = ver;
+                }
+                "Binary" => binary = field.words().map(String::from).collect(),
+                "Architecture" => architecture = value(),
+                "Version" => version = value(),
+                "Binary-Only-Changes" => {
+                    let mut lines = field
+                        .lines
+                        .iter()
+                        .map(|(_, line)| *line)
+                        .collect::<Vec<_>>();
+                    if !field.value.is_empty() {
+                        lines.insert(0, field.value);
                     }
+                    binary_only_changes = Some(lines.join("\n"));
                 }
-            } else {
-                // Regular key/value
-                if let Some((key, value)) = line.split_once(": ") {
-                    match key {
-                        "Source" => {
-                            let (src, _) = value.split_once(' ').unwrap_or((value, ""));
-                            source = Some(src.to_string());
+                name if name.starts_with("Checksums-") => {
+                    for (num, line) in &field.lines {
+                        let checksum = checksum_from_line(line)
+                            .ok_or(ParseError::InvalidChecksum { line: *num })?;
+                        artifacts.insert(checksum.file_name.clone());
+                        match name {
+                            "Checksums-Md5" => checksums_md5.push(checksum),
+                            "Checksums-Sha1" => checksums_sha1.push(checksum),
+                            "Checksums-Sha256" => checksums_sha256.push(checksum),
+                            _ => (),
                         }
-                        "Architecture" => architecture = Some(value.to_string()),
-                        "Version" => version = Some(value.to_string()),
-                        _ => (),
+                    }
+                    if !matches!(
+                        name,
+                        "Checksums-Md5" | "Checksums-Sha1" | "Checksums-Sha256"
+                    ) {
+                        other_fields.push((name.to_string(), field.raw_value()));
+                    }
+                }
+                "Build-Origin" => build_origin = value(),
+                "Build-Architecture" => build_architecture = value(),
+                "Build-Date" => build_date = value(),
+                "Build-Path" => build_path = value(),
+                "Build-Tainted-By" => build_tainted_by = field.words().map(String::from).collect(),
+                "Installed-Build-Depends" => {
+                    for (num, line) in field.all_lines() {
+                        for pkg in line.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                            let pkg = installed_package_from_str(pkg)
+                                .ok_or_else(|| field.invalid_value(num))?;
+                            installed_build_depends.push(pkg);
+                        }
+                    }
+                }
+                "Environment" => {
+                    for (num, line) in &field.lines {
+                        let (key, value) = line
+                            .trim()
+                            .split_once('=')
+                            .ok_or_else(|| field.invalid_value(*num))?;
+                        environment.insert(key.to_string(), unquote(value));
                     }
                 }
+                name => other_fields.push((name.to_string(), field.raw_value())),
             }
         }
 
+        let version = version.ok_or(ParseError::MissingField { field: "Version" })?;
+        // only keep the source version if it adds any information
+        let source_version = source_version.filter(|v| *v != version);
+
         Ok(Buildinfo {
-            source: source.context("Missing `source` field in buildinfo")?,
-            architecture: architecture.context("Missing `architecture` field in buildinfo")?,
-            version: version.context("Missing `version` field in buildinfo")?,
+            format,
+            source: source.ok_or(ParseError::MissingField { field: "Source" })?,
+            source_version,
+            binary,
+            architecture: architecture.ok_or(ParseError::MissingField {
+                field: "Architecture",
+            })?,
+            version,
+            binary_only_changes,
             artifacts,
+            checksums_md5,
+            checksums_sha1,
+            checksums_sha256,
+            build_origin,
+            build_architecture,
+            build_date,
+            build_path,
+            build_tainted_by,
+            installed_build_depends,
+            environment,
+            other_fields,
+            field_order,
         })
     }
 }
 
-fn artifact_from_checksum_line(line: &str) -> Option<String> {
+/// The field order used by dpkg-genbuildinfo
+const FIELD_ORDER: &[&str] = &[
+    "Format",
+    "Source",
+    "Binary",
+    "Architecture",
+    "Version",
+    "Binary-Only-Changes",
+    "Checksums-Md5",
+    "Checksums-Sha1",
+    "Checksums-Sha256",
+    "Build-Origin",
+    "Build-Architecture",
+    "Build-Date",
+    "Build-Path",
+    "Build-Tainted-By",
+    "Installed-Build-Depends",
+    "Environment",
+];
+
+impl Buildinfo {
+    /// Whether a known field has a value and needs to be written
+    fn has_field(&self, name: &str) -> bool {
+        match name {
+            "Format" => self.format.is_some(),
+            "Source" | "Architecture" | "Version" => true,
+            "Binary" => !self.binary.is_empty(),
+            "Binary-Only-Changes" => self.binary_only_changes.is_some(),
+            "Checksums-Md5" => !self.checksums_md5.is_empty(),
+            "Checksums-Sha1" => !self.checksums_sha1.is_empty(),
+            "Checksums-Sha256" => !self.checksums_sha256.is_empty(),
+            "Build-Origin" => self.build_origin.is_some(),
+            "Build-Architecture" => self.build_architecture.is_some(),
+            "Build-Date" => self.build_date.is_some(),
+            "Build-Path" => self.build_path.is_some(),
+            "Build-Tainted-By" => !self.build_tainted_by.is_empty(),
+            "Installed-Build-Depends" => !self.installed_build_depends.is_empty(),
+            "Environment" => !self.environment.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// The names of all fields to write, `field_order` with missing known fields inserted after
+    /// the closest field that precedes them in the dpkg order
+    fn output_order(&self) -> Vec<&str> {
+        let mut order = self
+            .field_order
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        for (idx, name) in FIELD_ORDER.iter().enumerate() {
+            if order.contains(name) || !self.has_field(name) {
+                continue;
+            }
+            let pos = FIELD_ORDER[..idx]
+                .iter()
+                .rev()
+                .find_map(|prev| order.iter().position(|n| n == prev))
+                .map(|pos| pos + 1)
+                .unwrap_or(0);
+            order.insert(pos, name);
+        }
+        for (name, _) in &self.other_fields {
+            if !order.contains(&name.as_str()) {
+                order.push(name);
+            }
+        }
+        order
+    }
+
+    fn write_field(&self, f: &mut fmt::Formatter, name: &str) -> fmt::Result {
+        let optional = |f: &mut fmt::Formatter, value: &Option<String>| -> fmt::Result {
+            match value {
+                Some(value) => writeln!(f, "{}: {}", name, value),
+                None => Ok(()),
+            }
+        };
+        let checksums = |f: &mut fmt::Formatter, checksums: &[ArtifactChecksum]| -> fmt::Result {
+            if !checksums.is_empty() {
+                writeln!(f, "{}:", name)?;
+                for c in checksums {
+                    writeln!(f, " {} {} {}", c.hash, c.size, c.file_name)?;
+                }
+            }
+            Ok(())
+        };
+
+        match name {
+            "Format" => optional(f, &self.format)?,
+            "Source" => match &self.source_version {
+                Some(version) => writeln!(f, "Source: {} ({})", self.source, version)?,
+                None => writeln!(f, "Source: {}", self.source)?,
+            },
+            "Binary" => {
+                if !self.binary.is_empty() {
+                    writeln!(f, "Binary: {}", self.binary.join(" "))?;
+                }
+            }
+            "Architecture" => writeln!(f, "Architecture: {}", self.architecture)?,
+            "Version" => writeln!(f, "Version: {}", self.version)?,
+            "Binary-Only-Changes" => {
+                if let Some(changes) = &self.binary_only_changes {
+                    writeln!(f, "Binary-Only-Changes:")?;
+                    for line in changes.lines() {
+                        writeln!(f, " {}", line)?;
+                    }
+                }
+            }
+            "Checksums-Md5" => checksums(f, &self.checksums_md5)?,
+            "Checksums-Sha1" => checksums(f, &self.checksums_sha1)?,
+            "Checksums-Sha256" => checksums(f, &self.checksums_sha256)?,
+            "Build-Origin" => optional(f, &self.build_origin)?,
+            "Build-Architecture" => optional(f, &self.build_architecture)?,
+            "Build-Date" => optional(f, &self.build_date)?,
+            "Build-Path" => optional(f, &self.build_path)?,
+            "Build-Tainted-By" => {
+                if !self.build_tainted_by.is_empty() {
+                    writeln!(f, "Build-Tainted-By:")?;
+                    for reason in &self.build_tainted_by {
+                        writeln!(f, " {}", reason)?;
+                    }
+                }
+            }
+            "Installed-Build-Depends" => {
+                if !self.installed_build_depends.is_empty() {
+                    writeln!(f, "Installed-Build-Depends:")?;
+                    let mut iter = self.installed_build_depends.iter().peekable();
+                    while let Some(pkg) = iter.next() {
+                        let sep = if iter.peek().is_some() { "," } else { "" };
+                        writeln!(f, " {} (= {}){}", pkg.name, pkg.version, sep)?;
+                    }
+                }
+            }
+            "Environment" => {
+                if !self.environment.is_empty() {
+                    writeln!(f, "Environment:")?;
+                    for (key, value) in &self.environment {
+                        writeln!(f, " {}=\"{}\"", key, value.replace('"', "\\\""))?;
+                    }
+                }
+            }
+            _ => {
+                let value = self
+                    .other_fields
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, value)| value);
+                if let Some(value) = value {
+                    let mut lines = value.split('\n');
+                    match lines.next().filter(|line| !line.is_empty()) {
+                        Some(first) => writeln!(f, "{}: {}", name, first)?,
+                        None => writeln!(f, "{}:", name)?,
+                    }
+                    for line in lines {
+                        writeln!(f, " {}", line)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Buildinfo {
+    /// Write the buildinfo without signature, with all fields in the order they were read.
+    ///
+    /// Known fields that weren't read, e.g. a `Binary-Only-Changes` that was added later, are
+    /// placed at their position in the order used by dpkg-genbuildinfo. Fields that were added
+    /// to `other_fields` are written last.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for name in self.output_order() {
+            self.write_field(f, name)?;
+        }
+        Ok(())
+    }
+}
+
+fn checksum_from_line(line: &str) -> Option<ArtifactChecksum> {
     let line = line.trim();
-    let (_hash, line) = line.split_once(' ')?;
-    let (_size, line) = line.split_once(' ')?;
-    Some(line.to_string())
+    let (hash, line) = line.split_once(' ')?;
+    let (size, line) = line.split_once(' ')?;
+    Some(ArtifactChecksum {
+        hash: hash.to_string(),
+        size: size.parse().ok()?,
+        file_name: line.to_string(),
+    })
+}
+
+// `name (= version)`, the name may contain an architecture qualifier like `libc6:amd64`
+fn installed_package_from_str(s: &str) -> Option<InstalledPackage> {
+    let (name, version) = s.split_once('(')?;
+    let version = version.strip_suffix(')')?.trim().strip_prefix('=')?.trim();
+    Some(InstalledPackage {
+        name: name.trim().to_string(),
+        version: version.to_string(),
+    })
+}
+
+// dpkg-genbuildinfo writes `KEY="value"` with `"` escaped as `\"`
+fn unquote(value: &str) -> String {
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value);
+    value.replace("\\\"", "\"")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn parse_buildinfo_sniffglue() {
-        let data = r#"-----BEGIN PGP SIGNED MESSAGE-----
+    const SNIFFGLUE: &str = r#"-----BEGIN PGP SIGNED MESSAGE-----
 Hash: SHA512
 
 Format: 1.0
@@ -457,25 +1031,8 @@ rLtioF1ivcIxRqx/8541vxERmntZ8Ud0bd1a5DJVGoMAh6AFHjMlqNPJNC3pUYdv
 =eBQQ
 -----END PGP SIGNATURE-----
 "#;
-        let buildinfo = Buildinfo::from_str(data).unwrap();
-        let mut artifacts = HashSet::new();
-        artifacts.insert("librust-sniffglue-dev_0.14.0-2_amd64.deb".to_string());
-        artifacts.insert("sniffglue-dbgsym_0.14.0-2_amd64.deb".to_string());
-        artifacts.insert("sniffglue_0.14.0-2_amd64.deb".to_string());
-        assert_eq!(
-            buildinfo,
-            Buildinfo {
-                source: "rust-sniffglue".to_string(),
-                architecture: "amd64".to_string(),
-                version: "0.14.0-2".to_string(),
-                artifacts,
-            }
-        );
-    }
 
-    #[test]
-    fn parse_buildinfo_courier() {
-        let data = r#"-----BEGIN PGP SIGNED MESSAGE-----
+    const COURIER: &str = r#"-----BEGIN PGP SIGNED MESSAGE-----
 Hash: SHA512
 
 Format: 1.0
@@ -877,7 +1434,105 @@ lB08tKl42cWzrmGTV0hCxtZlcwXzx+IjsXsva0bnoA8I3Szs8IOXOXLlRXiGcrUN
 =gVYV
 -----END PGP SIGNATURE-----
 "#;
-        let buildinfo = Buildinfo::from_str(data).unwrap();
+
+    #[test]
+    fn parse_buildinfo_sniffglue() {
+        let buildinfo = Buildinfo::from_str(SNIFFGLUE).unwrap();
+        let mut artifacts = HashSet::new();
+        artifacts.insert("librust-sniffglue-dev_0.14.0-2_amd64.deb".to_string());
+        artifacts.insert("sniffglue-dbgsym_0.14.0-2_amd64.deb".to_string());
+        artifacts.insert("sniffglue_0.14.0-2_amd64.deb".to_string());
+        assert_eq!(buildinfo.source, "rust-sniffglue");
+        assert_eq!(buildinfo.architecture, "amd64");
+        assert_eq!(buildinfo.version, "0.14.0-2");
+        assert_eq!(buildinfo.artifacts, artifacts);
+        assert_eq!(buildinfo.format.as_deref(), Some("1.0"));
+        assert_eq!(buildinfo.source_version, None);
+        assert_eq!(
+            buildinfo.binary,
+            &["librust-sniffglue-dev", "sniffglue", "sniffglue-dbgsym"]
+        );
+        assert_eq!(buildinfo.binary_only_changes, None);
+        assert_eq!(
+            buildinfo.checksums_md5.last(),
+            Some(&ArtifactChecksum {
+                hash: "177f9229266ad5eef3fb42fff0c07345".to_string(),
+                size: 732980,
+                file_name: "sniffglue_0.14.0-2_amd64.deb".to_string(),
+            })
+        );
+        assert_eq!(
+            buildinfo.checksums_sha1.last().map(|c| c.hash.as_str()),
+            Some("d2e4f34a46527effd3375764463d2c1bbe3eeecc")
+        );
+        assert_eq!(buildinfo.build_origin.as_deref(), Some("Debian"));
+        assert_eq!(buildinfo.build_architecture.as_deref(), Some("amd64"));
+        assert_eq!(
+            buildinfo.build_date.as_deref(),
+            Some("Mon, 06 Dec 2021 21:35:27 +0000")
+        );
+        assert_eq!(
+            buildinfo.build_path.as_deref(),
+            Some("/build/rust-sniffglue-1KDXF6/rust-sniffglue-0.14.0")
+        );
+        assert!(buildinfo.build_tainted_by.is_empty());
+        assert_eq!(buildinfo.installed_build_depends.len(), 336);
+        assert_eq!(
+            buildinfo.installed_build_depends[0],
+            InstalledPackage {
+                name: "autoconf".to_string(),
+                version: "2.71-2".to_string(),
+            }
+        );
+        assert_eq!(
+            buildinfo.installed_build_depends[335],
+            InstalledPackage {
+                name: "zlib1g".to_string(),
+                version: "1:1.2.11.dfsg-2".to_string(),
+            }
+        );
+        let environment = buildinfo
+            .environment
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            environment,
+            &[
+                ("DEB_BUILD_OPTIONS", "parallel=4"),
+                ("LC_ALL", "C.UTF-8"),
+                ("LC_COLLATE", "C.UTF-8"),
+                ("SOURCE_DATE_EPOCH", "1638820316"),
+            ]
+        );
+        assert_eq!(
+            buildinfo.checksums_sha256,
+            vec![
+                ArtifactChecksum {
+                    hash: "c452054c216359ef44adc9a5d35870d707f47e503051dfcb736f47df17058961"
+                        .to_string(),
+                    size: 125404,
+                    file_name: "librust-sniffglue-dev_0.14.0-2_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "214817662f43ec4ae0766dd23700a694c45985cb03d28fe82a791a61202e0705"
+                        .to_string(),
+                    size: 8995232,
+                    file_name: "sniffglue-dbgsym_0.14.0-2_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "448c781a9e594227bc9f0d6c65b8beba2b3add68d3583020de188d4cfa365b40"
+                        .to_string(),
+                    size: 732980,
+                    file_name: "sniffglue_0.14.0-2_amd64.deb".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_buildinfo_courier() {
+        let buildinfo = Buildinfo::from_str(COURIER).unwrap();
         let mut artifacts = HashSet::new();
         artifacts.insert("courier-base-dbgsym_1.0.16-3+b1_amd64.deb".to_string());
         artifacts.insert("courier-base_1.0.16-3+b1_amd64.deb".to_string());
@@ -898,14 +1553,331 @@ lB08tKl42cWzrmGTV0hCxtZlcwXzx+IjsXsva0bnoA8I3Szs8IOXOXLlRXiGcrUN
         artifacts.insert("courier-webadmin_1.0.16-3+b1_amd64.deb".to_string());
         artifacts.insert("sqwebmail-dbgsym_6.0.5+1.0.16-3+b1_amd64.deb".to_string());
         artifacts.insert("sqwebmail_6.0.5+1.0.16-3+b1_amd64.deb".to_string());
+        assert_eq!(buildinfo.source, "courier");
+        assert_eq!(buildinfo.architecture, "amd64");
+        assert_eq!(buildinfo.version, "1.0.16-3+b1");
+        assert_eq!(buildinfo.artifacts, artifacts);
+        assert_eq!(buildinfo.source_version.as_deref(), Some("1.0.16-3"));
+        assert_eq!(buildinfo.binary.len(), 19);
         assert_eq!(
-            buildinfo,
-            Buildinfo {
-                source: "courier".to_string(),
-                architecture: "amd64".to_string(),
-                version: "1.0.16-3+b1".to_string(),
-                artifacts,
-            }
+            buildinfo.binary_only_changes.as_deref(),
+            Some(
+                "courier (1.0.16-3+b1) sid; urgency=low, binary-only=yes
+.
+  * Binary-only non-maintainer upload for amd64; no source changes.
+  * Rebuild against libidn12
+.
+ -- amd64 / i386 Build Daemon (x86-ubc-01) <buildd_amd64-x86-ubc-01@buildd.debian.org>  Sun, 22 Aug 2021 22:12:19 +0000"
+            )
+        );
+        assert_eq!(buildinfo.checksums_md5.len(), 19);
+        assert_eq!(buildinfo.checksums_sha1.len(), 19);
+        assert_eq!(buildinfo.installed_build_depends.len(), 299);
+        assert_eq!(
+            buildinfo
+                .environment
+                .get("SOURCE_DATE_EPOCH")
+                .map(String::as_str),
+            Some("1629670339")
+        );
+        assert_eq!(
+            buildinfo.checksums_sha256,
+            vec![
+                ArtifactChecksum {
+                    hash: "786673efeab0460e94d1a1e60f36aa8732bb4535ae85746dee471a6db49fb676"
+                        .to_string(),
+                    size: 459456,
+                    file_name: "courier-base-dbgsym_1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "688b7c11b8ec92514929d37e207681e4b9ac754db9e8cf0ab0632374433eed7e"
+                        .to_string(),
+                    size: 328148,
+                    file_name: "courier-base_1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "6cb78e731f845dd98ab792c43fc01a6dc3416140b08d2db00e7415eff5973527"
+                        .to_string(),
+                    size: 137492,
+                    file_name: "courier-faxmail_1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "c90cc58b7c957b90120b606f39422315bca05293defacc1d4cf42ce4aa178128"
+                        .to_string(),
+                    size: 504672,
+                    file_name: "courier-imap-dbgsym_5.0.13+1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "67acfd8593f6c0a12a2681906e43dfe2872a694bd74e5438726646ec0e2af0a6"
+                        .to_string(),
+                    size: 277952,
+                    file_name: "courier-imap_5.0.13+1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "a146803c918d1160ca4681bd62b3bc61e14f15bed0cd483ae66916e7b279c57a"
+                        .to_string(),
+                    size: 31896,
+                    file_name: "courier-ldap-dbgsym_1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "733c1e1f620b416fb107e115fc8fa3cbe511d1e47ce1e171e1ffb8b5b1cecd05"
+                        .to_string(),
+                    size: 142284,
+                    file_name: "courier-ldap_1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "aaf96264630a4526c7aff2502a8015c0d409ca6e772213d7b9014cce3f1ecb63"
+                        .to_string(),
+                    size: 2975168,
+                    file_name: "courier-mlm-dbgsym_1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "209018400fd2dfa4caf4ede13a8865a110a9dec1c387e8eb9e45e2cd0550b771"
+                        .to_string(),
+                    size: 390264,
+                    file_name: "courier-mlm_1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "5a513509987478410e297f99be9d0d644f999928c7bc9ad1e854ad25473eeee0"
+                        .to_string(),
+                    size: 3233060,
+                    file_name: "courier-mta-dbgsym_1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "a05a38e8aa0986067b40d1f82dbf59732c6f4697ea91d8717ef0ea88b388ae6a"
+                        .to_string(),
+                    size: 634364,
+                    file_name: "courier-mta_1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "254defd40412e5f6a09d4ca4784b07255f617d2a1bb2ed1f7deac6d67a1b9771"
+                        .to_string(),
+                    size: 148160,
+                    file_name: "courier-pcp-dbgsym_1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "7b118060d17983ff0a070825819a5b6ac46bad0262288e5f63c7bd0baefcf72d"
+                        .to_string(),
+                    size: 169328,
+                    file_name: "courier-pcp_1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "655cd79a41636291ed2eb4be416828d14d7d44c55cebeb139ddce65d33390dcf"
+                        .to_string(),
+                    size: 134468,
+                    file_name: "courier-pop-dbgsym_1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "efc865b63f19efda4feb15917789b9390667d973ac18d7aa6e641290a7ba8461"
+                        .to_string(),
+                    size: 179872,
+                    file_name: "courier-pop_1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "e7f7abf143674c7ac1fe5a6c0c21ac2d9505c3ade37625e7623a6d13ccc6e45f"
+                        .to_string(),
+                    size: 3992,
+                    file_name: "courier-webadmin-dbgsym_1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "bc65ecb8eac668c0c5fe18c6784217dd67541d2347899e0096b4b2f9f2ab0059"
+                        .to_string(),
+                    size: 147916,
+                    file_name: "courier-webadmin_1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "e48e7fb6a38b6dd2057f61db875368cf5d175bc5b012c5b88eba8f4c15b4321e"
+                        .to_string(),
+                    size: 1008928,
+                    file_name: "sqwebmail-dbgsym_6.0.5+1.0.16-3+b1_amd64.deb".to_string(),
+                },
+                ArtifactChecksum {
+                    hash: "51ebf109a5257b34a521a68967db5b328d2301ad9e7585ccf797dfb549ed5e6e"
+                        .to_string(),
+                    size: 496944,
+                    file_name: "sqwebmail_6.0.5+1.0.16-3+b1_amd64.deb".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_buildinfo_tainted() {
+        let data = r#"Format: 1.0
+Source: hello
+Binary: hello
+Architecture: amd64
+Version: 2.10-2
+Checksums-Sha256:
+ 8dc43fd4b3a4c3c1b6e1e4fd5fdc3bc46c1bd1e6f5e5b2e3fb6a4a0c28e8d1f0 56132 hello_2.10-2_amd64.deb
+Build-Tainted-By:
+ merged-usr-via-aliased-dirs
+ usr-local-has-programs
+Installed-Build-Depends:
+ libc6:amd64 (= 2.33-1), make (= 4.3-4.1)
+Environment:
+ DEB_BUILD_OPTIONS="nocheck \"parallel=4\""
+"#;
+        let buildinfo = Buildinfo::from_str(data).unwrap();
+        assert_eq!(
+            buildinfo.build_tainted_by,
+            &["merged-usr-via-aliased-dirs", "usr-local-has-programs"]
+        );
+        assert_eq!(
+            buildinfo.installed_build_depends,
+            &[
+                InstalledPackage {
+                    name: "libc6:amd64".to_string(),
+                    version: "2.33-1".to_string(),
+                },
+                InstalledPackage {
+                    name: "make".to_string(),
+                    version: "4.3-4.1".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            buildinfo
+                .environment
+                .get("DEB_BUILD_OPTIONS")
+                .map(String::as_str),
+            Some("nocheck \"parallel=4\"")
+        );
+        assert_eq!(buildinfo.format.as_deref(), Some("1.0"));
+        assert!(buildinfo.checksums_md5.is_empty());
+    }
+
+    fn assert_roundtrip(data: &str) {
+        let buildinfo = Buildinfo::from_str(data).unwrap();
+        let expected = pgp::strip_signature(data);
+        assert_eq!(buildinfo.to_string(), format!("{}\n", expected.trim_end()));
+        assert_eq!(
+            Buildinfo::from_str(&buildinfo.to_string()).unwrap(),
+            buildinfo
+        );
+    }
+
+    #[test]
+    fn roundtrip_buildinfo_sniffglue() {
+        assert_roundtrip(SNIFFGLUE);
+    }
+
+    #[test]
+    fn roundtrip_buildinfo_courier() {
+        assert_roundtrip(COURIER);
+    }
+
+    #[test]
+    fn roundtrip_unknown_fields_in_place() {
+        let data = "Format: 1.0
+Source: hello
+Binary: hello
+Architecture: amd64
+Version: 2.10-2
+X-Build-Notes:
+ first line
+ second line
+Checksums-Sha256:
+ 35b1508eeee9c1dfba798c4c04304ef0f266990f936a51f165571edf53325cbc 56132 hello_2.10-2_amd64.deb
+Build-Origin: Debian
+Build-Kernel-Version: 5.10.0-10-amd64 #1 SMP Debian 5.10.84-1 (2021-12-08)
+Build-Date: Tue, 14 Dec 2021 18:25:53 +0000
+Build-Architecture: amd64
+";
+        let buildinfo = Buildinfo::from_str(data).unwrap();
+        assert_eq!(
+            buildinfo.other_fields,
+            &[
+                (
+                    "X-Build-Notes".to_string(),
+                    "\nfirst line\nsecond line".to_string()
+                ),
+                (
+                    "Build-Kernel-Version".to_string(),
+                    "5.10.0-10-amd64 #1 SMP Debian 5.10.84-1 (2021-12-08)".to_string()
+                ),
+            ]
+        );
+        let out = buildinfo.to_string();
+        assert_eq!(out, data);
+        assert_eq!(Buildinfo::from_str(&out).unwrap(), buildinfo);
+    }
+
+    #[test]
+    fn serialize_patched_binnmu() {
+        let mut buildinfo = Buildinfo::from_str(SNIFFGLUE).unwrap();
+        buildinfo.source_version = Some(buildinfo.version.clone());
+        buildinfo.version = "0.14.0-2+b1".to_string();
+        buildinfo.binary_only_changes = Some(
+            "rust-sniffglue (0.14.0-2+b1) sid; urgency=low, binary-only=yes\n.\n  * Rebuild"
+                .to_string(),
+        );
+        let out = buildinfo.to_string();
+        assert!(out.contains(
+            "Source: rust-sniffglue (0.14.0-2)
+Binary: librust-sniffglue-dev sniffglue sniffglue-dbgsym
+Architecture: amd64
+Version: 0.14.0-2+b1
+Binary-Only-Changes:
+ rust-sniffglue (0.14.0-2+b1) sid; urgency=low, binary-only=yes
+ .
+   * Rebuild
+Checksums-Md5:
+"
+        ));
+        let reparsed = Buildinfo::from_str(&out).unwrap();
+        assert_eq!(reparsed.binary_only_changes, buildinfo.binary_only_changes);
+        assert_eq!(reparsed.to_string(), out);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = Buildinfo::from_str("Format: 1.0\nSource: hello\nArchitecture: amd64\n");
+        assert_eq!(err, Err(ParseError::MissingField { field: "Version" }));
+
+        let err = Buildinfo::from_str("Source: hello\nVersion: 1\nSource: hello\n");
+        assert_eq!(
+            err,
+            Err(ParseError::DuplicateField {
+                line: 3,
+                field: "Source".to_string()
+            })
+        );
+
+        let err = Buildinfo::from_str("Source: hello\nChecksums-Sha256:\n 1234 hello.deb\n");
+        assert_eq!(err, Err(ParseError::InvalidChecksum { line: 3 }));
+
+        let err = Buildinfo::from_str(" continued\n");
+        assert_eq!(err, Err(ParseError::InvalidLine { line: 1 }));
+
+        let err = Buildinfo::from_bytes(b"Source: hello\nVersion: 1\xff\n");
+        assert_eq!(err, Err(ParseError::NotUtf8 { line: 2 }));
+        assert_eq!(err.unwrap_err().kind(), "not-utf8");
+    }
+
+    #[test]
+    fn parse_errors_signed() {
+        // line numbers refer to the signed document
+        let err = Buildinfo::from_str(&COURIER.replace("Build-Path:", "Build-Origin:"));
+        assert_eq!(
+            err,
+            Err(ParseError::DuplicateField {
+                line: 79,
+                field: "Build-Origin".to_string()
+            })
+        );
+
+        let data = COURIER.replace("-----END PGP SIGNATURE-----\n", "");
+        assert_eq!(
+            Buildinfo::from_str(&data),
+            Err(ParseError::InvalidArmor {
+                line: 400,
+                reason: "missing end of signature"
+            })
         );
+        let data = &COURIER[..COURIER.find("-----BEGIN PGP SIGNATURE-----").unwrap()];
+        let err = Buildinfo::from_str(data).unwrap_err();
+        assert_eq!(err.kind(), "invalid-armor");
+        assert_eq!(err.line(), Some(385));
     }
 }
@@ -0,0 +1,111 @@
+use crate::errors::*;
+use bzip2::read::BzDecoder;
+use flate2::read::GzDecoder;
+use std::io::prelude::*;
+use xz2::read::XzDecoder;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Xz,
+    Gzip,
+    Bzip2,
+    Zstd,
+    None,
+}
+
+impl Compression {
+    pub fn from_magic(bytes: &[u8]) -> Option<Compression> {
+        if bytes.starts_with(b"\xfd7zXZ\x00") {
+            Some(Compression::Xz)
+        } else if bytes.starts_with(b"\x1f\x8b") {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(b"BZh") {
+            Some(Compression::Bzip2)
+        } else if bytes.starts_with(b"\x28\xb5\x2f\xfd") {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    pub fn from_extension(name: &str) -> Option<Compression> {
+        let name = name.trim_end_matches('/');
+        let (_, ext) = name.rsplit_once('.')?;
+        match ext {
+            "xz" => Some(Compression::Xz),
+            "gz" => Some(Compression::Gzip),
+            "bz2" => Some(Compression::Bzip2),
+            "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Detect the compression from the magic bytes, fall back to the file extension.
+    ///
+    /// If neither of them is recognized the data is assumed to be uncompressed.
+    pub fn detect(bytes: &[u8], name: &str) -> Compression {
+        Self::from_magic(bytes)
+            .or_else(|| Self::from_extension(name))
+            .unwrap_or(Compression::None)
+    }
+
+    pub fn decoder<'a, R: Read + Send + 'a>(self, reader: R) -> Result<Box<dyn Read + Send + 'a>> {
+        debug!("Using decoder for compression: {:?}", self);
+        let reader: Box<dyn Read + Send> = match self {
+            Compression::Xz => Box::new(XzDecoder::new(reader)),
+            Compression::Gzip => Box::new(GzDecoder::new(reader)),
+            Compression::Bzip2 => Box::new(BzDecoder::new(reader)),
+            Compression::Zstd => Box::new(
+                zstd::stream::read::Decoder::new(reader).context("Failed to setup zstd decoder")?,
+            ),
+            Compression::None => Box::new(reader),
+        };
+        Ok(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_magic() {
+        assert_eq!(
+            Compression::detect(b"\xfd7zXZ\x00\x00\x04", "Packages"),
+            Compression::Xz
+        );
+        assert_eq!(
+            Compression::detect(b"\x1f\x8b\x08\x00", "Packages.xz"),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::detect(b"BZh91AY&SY", "Packages"),
+            Compression::Bzip2
+        );
+        assert_eq!(
+            Compression::detect(b"\x28\xb5\x2f\xfd\x04", "Packages"),
+            Compression::Zstd
+        );
+        assert_eq!(
+            Compression::detect(b"Package: sniffglue\n", "Packages"),
+            Compression::None
+        );
+    }
+
+    #[test]
+    fn test_detect_extension_fallback() {
+        assert_eq!(Compression::detect(b"", "Packages.gz"), Compression::Gzip);
+        assert_eq!(
+            Compression::detect(
+                b"",
+                "http://deb.debian.org/debian/dists/sid/main/binary-amd64/Packages.zst"
+            ),
+            Compression::Zstd
+        );
+        assert_eq!(
+            Compression::detect(b"", "./Packages.bz2"),
+            Compression::Bzip2
+        );
+        assert_eq!(Compression::detect(b"", "./Packages"), Compression::None);
+    }
+}
@@ -0,0 +1,723 @@
+use crate::buildinfo;
+use crate::db::Database;
+use crate::deb::{self, PackagesIndex};
+use crate::errors::*;
+use crate::http_cache;
+use crate::models;
+use crate::pgp::{Keyring, Verification};
+use crate::release;
+use crate::report::{FailedUrl, MissingReason, Report};
+use crate::resolver::{Resolved, Resolver};
+use crate::utils::HttpClient;
+use crate::version;
+use futures::future;
+use futures::stream::{self, StreamExt};
+use rebuilderd_common::{PkgArtifact, PkgGroup};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, OnceCell};
+
+// Number of decoded packages that are waiting to be resolved
+const PACKAGES_QUEUE_SIZE: usize = 256;
+
+/// Settings of a crawl, these are the command line options of the binary
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// The Debian mirror the packages are downloaded from, e.g. `https://deb.debian.org/debian`
+    pub source: String,
+    /// The distro in rebuilderd, e.g. `debian`
+    pub distro: String,
+    /// The component to import, e.g. `main` or `contrib`
+    pub suite: String,
+    /// The public location of the buildinfo files, their urls are stored and used in the build groups
+    pub buildinfo_base: String,
+    /// Read the buildinfo files from this local directory or http mirror instead of `buildinfo_base`
+    pub buildinfo_mirror: Option<String>,
+    /// Only use buildinfo files that are already in the database
+    pub skip_crawl: bool,
+    /// Crawl packages without buildinfo again after this time, even if the directory listing didn't change
+    pub recheck_after: Duration,
+    /// Fail if a buildinfo file can't be parsed, instead of skipping it
+    pub strict: bool,
+    /// Don't use buildinfo files without a valid signature, only used with a keyring
+    pub exclude_unsigned: bool,
+    /// How many source packages to crawl in parallel
+    pub concurrency: usize,
+}
+
+impl Config {
+    /// The `input_url` used for build groups without buildinfo file
+    pub fn missing_buildinfo_url(&self, src: &str, version: &str) -> String {
+        format!(
+            "{}/missing-buildinfo/{}/{}",
+            self.buildinfo_base.trim_end_matches('/'),
+            src,
+            version
+        )
+    }
+}
+
+/// The result of a crawl
+pub struct Output {
+    /// The build groups for rebuilderd, sorted by name, version and architecture
+    pub groups: Vec<PkgGroup>,
+    /// Everything that went wrong, written with --report
+    pub report: Report,
+}
+
+// The fingerprint of the signer, or why the signature isn't valid
+type Signer = std::result::Result<String, String>;
+
+// Checks the signatures of buildinfo files with gpgv on the blocking thread pool. A buildinfo
+// file that is found for multiple packages at the same time is only checked once
+struct Verifier<'a> {
+    db: &'a Database,
+    keyring: &'a Keyring,
+    checked: Mutex<HashMap<i32, Arc<OnceCell<Signer>>>>,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(db: &'a Database, keyring: &'a Keyring) -> Self {
+        Verifier {
+            db,
+            keyring,
+            checked: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // The result is stored, gpgv only runs again if the keyring changed. Returns why the
+    // signature isn't valid, if it isn't
+    async fn verify(&self, buildinfo: &mut models::buildinfo::Buildinfo) -> Result<Option<String>> {
+        if buildinfo.verified_with.as_deref() == Some(self.keyring.id()) {
+            let reason = "No valid signature, checked in an earlier run";
+            return Ok(buildinfo.signer.is_none().then(|| reason.to_string()));
+        }
+
+        let cell = self
+            .checked
+            .lock()
+            .unwrap()
+            .entry(buildinfo.id)
+            .or_default()
+            .clone();
+        let signer = cell
+            .get_or_try_init(|| async {
+                let keyring = self.keyring.clone();
+                let content = buildinfo.content.clone();
+                let verification = tokio::task::spawn_blocking(move || {
+                    keyring.verify_clearsigned(content.as_bytes())
+                })
+                .await??;
+                let signer = match verification {
+                    Verification::Valid { fingerprint } => {
+                        debug!(
+                            "Buildinfo {:?} is signed by {:?}",
+                            buildinfo.url, fingerprint
+                        );
+                        Ok(fingerprint)
+                    }
+                    Verification::Unsigned => {
+                        warn!("Buildinfo is not signed: {:?}", buildinfo.url);
+                        Err("Not signed".to_string())
+                    }
+                    Verification::Invalid(err) => {
+                        warn!(
+                            "Buildinfo has invalid signature: {:?}: {}",
+                            buildinfo.url, err
+                        );
+                        Err(format!("Invalid signature: {}", err))
+                    }
+                };
+                self.db.set_buildinfo_verification(
+                    buildinfo.id,
+                    signer.as_deref().ok(),
+                    self.keyring.id(),
+                )?;
+                Ok::<_, Error>(signer)
+            })
+            .await?;
+
+        buildinfo.signer = signer.as_ref().ok().cloned();
+        buildinfo.verified_with = Some(self.keyring.id().to_string());
+        Ok(signer.as_ref().err().cloned())
+    }
+}
+
+/// Open the given Packages indexes, or look them up in the Release files of the mirror.
+///
+/// Release files are verified with the keyring, if one is given.
+pub async fn open_indexes(
+    client: &HttpClient,
+    db: &Database,
+    keyring: Option<&Keyring>,
+    config: &Config,
+    packages_dbs: &[String],
+    releases: &[String],
+    architectures: &[String],
+) -> Result<Vec<PackagesIndex>> {
+    let mut indexes = Vec::new();
+    if !packages_dbs.is_empty() {
+        for url in packages_dbs {
+            let (blob, cache) = http_cache::fetch_blob(client, db, url).await?;
+            indexes.push(PackagesIndex {
+                url: url.to_string(),
+                sha256: blob.sha256,
+                cache,
+            });
+        }
+    } else {
+        if releases.is_empty() || architectures.is_empty() {
+            bail!("No package index given, use --packages-db or --release and --arch");
+        }
+
+        for release_name in releases {
+            let (url, release) =
+                release::fetch_release(client, &config.source, release_name, keyring).await?;
+            info!(
+                "Using release {:?} (suite={:?}, codename={:?}, date={:?})",
+                release_name, release.suite, release.codename, release.date
+            );
+            db.add_release(url, &release)?;
+
+            for arch in architectures {
+                let index = release::open_packages_index(
+                    client,
+                    db,
+                    &config.source,
+                    release_name,
+                    &release,
+                    &config.suite,
+                    arch,
+                )
+                .await?;
+                indexes.push(index);
+            }
+        }
+    }
+    Ok(indexes)
+}
+
+/// Find the buildinfo files of all packages in the indexes and generate the build groups
+pub async fn run(
+    db: &Database,
+    client: &HttpClient,
+    keyring: Option<&Keyring>,
+    config: &Config,
+    mut indexes: Vec<PackagesIndex>,
+) -> Result<Output> {
+    // Decode the indexes in the background while the packages are resolved, the channel is
+    // bounded so the decoder can't get too far ahead of the resolver
+    let (tx, mut pkgs) = mpsc::channel(PACKAGES_QUEUE_SIZE);
+    let caches = indexes
+        .iter_mut()
+        .filter_map(|index| index.cache.take())
+        .collect::<Vec<_>>();
+    let files = indexes
+        .into_iter()
+        .map(|index| Ok((db.blobs().open(&index.sha256)?, index.url)))
+        .collect::<Result<Vec<_>>>()?;
+    let reader = tokio::task::spawn_blocking(move || -> Result<()> {
+        for (file, url) in files {
+            let index = deb::read_compressed_packages_db(file, &url)?;
+            let mut num = 0;
+            for pkg in index {
+                let pkg =
+                    pkg.with_context(|| anyhow!("Failed to parse package index: {:?}", url))?;
+                if tx.blocking_send(pkg).is_err() {
+                    return Ok(());
+                }
+                num += 1;
+            }
+            info!("Found {} binary packages in index {:?}", num, url);
+        }
+        Ok(())
+    });
+
+    let mut without_buildinfo = Vec::new();
+    let mut unsigned_buildinfos = HashMap::new();
+    let mut checksum_mismatch = Vec::new();
+    let mut groups = HashMap::new();
+
+    let resolver = Resolver::new(
+        db,
+        client,
+        config.buildinfo_base.clone(),
+        config.skip_crawl,
+        config.recheck_after,
+        config.strict,
+    )
+    .buildinfo_mirror(config.buildinfo_mirror.clone());
+
+    let verifier = keyring.map(|keyring| Verifier::new(db, keyring));
+
+    let mut seen = HashSet::new();
+    let mut results = stream::poll_fn(|cx| pkgs.poll_recv(cx))
+        // Architecture: all packages show up in every index, only process them once
+        .filter(|pkg| future::ready(seen.insert((pkg.deb_folder.clone(), pkg.file_name.clone()))))
+        .map(|pkg| {
+            let resolver = &resolver;
+            let verifier = verifier.as_ref();
+            async move {
+                let resolved = match (resolver.resolve(&pkg).await, verifier) {
+                    (Ok(Resolved::Found(mut buildinfo)), Some(verifier)) => verifier
+                        .verify(&mut buildinfo)
+                        .await
+                        .map(|unsigned| (Resolved::Found(buildinfo), unsigned)),
+                    (resolved, _) => resolved.map(|resolved| (resolved, None)),
+                };
+                (pkg, resolved)
+            }
+        })
+        .buffer_unordered(config.concurrency.max(1));
+
+    while let Some((pkg, resolved)) = results.next().await {
+        let (resolved, unsigned) = resolved?;
+        let buildinfo = match resolved {
+            Resolved::Found(buildinfo) => buildinfo,
+            Resolved::Missing => {
+                without_buildinfo.push((pkg, MissingReason::NoBuildinfo));
+                continue;
+            }
+            Resolved::ChecksumMismatch => {
+                checksum_mismatch.push(pkg.file_name.clone());
+                without_buildinfo.push((pkg, MissingReason::ChecksumMismatch));
+                continue;
+            }
+            Resolved::ListingFailed => {
+                without_buildinfo.push((pkg, MissingReason::ListingFailed));
+                continue;
+            }
+            Resolved::DownloadFailed => {
+                without_buildinfo.push((pkg, MissingReason::DownloadFailed));
+                continue;
+            }
+        };
+
+        if let Some(reason) = unsigned {
+            unsigned_buildinfos
+                .entry(buildinfo.url.clone())
+                .or_insert(reason);
+            if config.exclude_unsigned {
+                without_buildinfo.push((pkg, MissingReason::Unsigned));
+                continue;
+            }
+        }
+
+        let group = groups
+            .entry(buildinfo.url.clone())
+            .or_insert_with(|| (buildinfo, HashMap::new()));
+        let list = group
+            .1
+            .entry(pkg.architecture.clone())
+            .or_insert_with(Vec::new);
+        list.push(pkg);
+    }
+
+    drop(results);
+    let mut report = resolver.into_report();
+    report.unsigned_buildinfos = unsigned_buildinfos
+        .into_iter()
+        .map(|(url, reason)| FailedUrl { url, reason })
+        .collect();
+    reader.await??;
+    for cache in caches {
+        cache.store(db)?;
+    }
+    info!("Found {} binary packages in total", seen.len());
+
+    if !without_buildinfo.is_empty() {
+        warn!(
+            "Packages missing buildinfo files: {}",
+            without_buildinfo.len()
+        );
+    }
+
+    if !checksum_mismatch.is_empty() {
+        warn!(
+            "Packages with a checksum that matches no known buildinfo file: {}",
+            checksum_mismatch.len()
+        );
+    }
+
+    if !report.unsigned_buildinfos.is_empty() {
+        warn!(
+            "Buildinfo files without valid signature: {}",
+            report.unsigned_buildinfos.len()
+        );
+    }
+
+    info!("Number of groups: {:?}", groups.len());
+    info!(
+        "Number of pkgs: {:?}",
+        groups.iter().fold(0, |acc, (_, x)| acc
+            + x.1.values().fold(0, |acc, x| acc + x.len()))
+    );
+
+    info!("Generating build groups...");
+    let mut out = Vec::new();
+    for (_, (model, group)) in groups {
+        // stored with an older version that accepted it, the parser may be stricter now
+        let buildinfo = match model.content.parse::<buildinfo::Buildinfo>() {
+            Ok(buildinfo) => buildinfo,
+            Err(err) => {
+                db.add_parse_failure(&model.url, None, &err)?;
+                if config.strict {
+                    bail!("Failed to parse buildinfo file {:?}: {}", model.url, err);
+                }
+                warn!(
+                    "Failed to parse buildinfo file {:?}, skipping: {}",
+                    model.url, err
+                );
+                for pkg in group.into_values().flatten() {
+                    without_buildinfo.push((pkg, MissingReason::ParseFailed));
+                }
+                continue;
+            }
+        };
+
+        for (architecture, pkgs) in group {
+            let mut artifacts = Vec::new();
+            for pkg in pkgs {
+                let url = pkg.url(&config.source);
+                artifacts.push(PkgArtifact {
+                    name: pkg.name,
+                    version: pkg.version,
+                    url,
+                });
+            }
+
+            out.push(PkgGroup {
+                name: buildinfo.source.clone(),
+                version: buildinfo.version.clone(),
+
+                distro: config.distro.clone(),
+                suite: config.suite.clone(),
+                architecture,
+
+                input_url: Some(model.url.clone()),
+                artifacts,
+            });
+        }
+    }
+
+    info!("Adding packages without buildinfo file...");
+    let mut missing_groups = HashMap::new();
+    for (pkg, reason) in without_buildinfo {
+        report.add_missing(&pkg, pkg.url(&config.source), reason);
+
+        let src = pkg.source.name.clone();
+        let version = pkg.buildinfo_version();
+
+        let key = format!("{src:?}-{version:?}");
+
+        let group = missing_groups
+            .entry(key)
+            .or_insert_with(|| (src, version, HashMap::new()));
+        let list = group
+            .2
+            .entry(pkg.architecture.clone())
+            .or_insert_with(Vec::new);
+        list.push(pkg);
+    }
+
+    for (_, (src, version, group)) in missing_groups {
+        for (architecture, pkgs) in group {
+            let mut artifacts = Vec::new();
+            for pkg in pkgs {
+                let url = pkg.url(&config.source);
+                artifacts.push(PkgArtifact {
+                    name: pkg.name,
+                    version: pkg.version,
+                    url,
+                });
+            }
+
+            let missing_buildinfo_url = config.missing_buildinfo_url(&src, &version);
+            out.push(PkgGroup {
+                name: src.clone(),
+                version: version.clone(),
+
+                distro: config.distro.clone(),
+                suite: config.suite.clone(),
+                architecture,
+
+                input_url: Some(missing_buildinfo_url),
+                artifacts,
+            });
+        }
+    }
+
+    info!("Sorting list...");
+    for group in &mut out {
+        group
+            .artifacts
+            .sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.url.cmp(&b.url)));
+    }
+    out.sort_by(|a, b| {
+        a.name
+            .cmp(&b.name)
+            .then_with(|| version::compare(&a.version, &b.version))
+            .then(a.architecture.cmp(&b.architecture))
+            .then_with(|| a.input_url.cmp(&b.input_url))
+    });
+
+    report.failed_parses = db.parse_failures()?.into_iter().map(From::from).collect();
+    report.sort();
+
+    Ok(Output {
+        groups: out,
+        report,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::{self, PendingSnapshot};
+
+    const BUILDINFO: &str = "Format: 1.0
+Source: rust-sniffglue
+Binary: sniffglue
+Architecture: amd64
+Version: 0.14.0-2
+Checksums-Sha256:
+ 448c781a9e594227bc9f0d6c65b8beba2b3add68d3583020de188d4cfa365b40 732980 sniffglue_0.14.0-2_amd64.deb
+Build-Origin: Debian
+";
+
+    const PACKAGES: &[u8] = b"Package: sniffglue
+Source: rust-sniffglue
+Version: 0.14.0-2
+Architecture: amd64
+Filename: pool/main/r/rust-sniffglue/sniffglue_0.14.0-2_amd64.deb
+SHA256: 448c781a9e594227bc9f0d6c65b8beba2b3add68d3583020de188d4cfa365b40
+
+Package: sniffglue-doc
+Source: rust-sniffglue
+Version: 0.14.0-2
+Architecture: all
+Filename: pool/main/r/rust-sniffglue/sniffglue-doc_0.14.0-2_all.deb
+SHA256: 4cb2fd9d2ad0e7ebb7c6e8fa4e2d3e5c0fd1cd6e49b3c1c1e1b6d6a54c4e8b1e
+";
+
+    fn config() -> Config {
+        Config {
+            source: "https://deb.debian.org/debian".to_string(),
+            distro: "debian".to_string(),
+            suite: "main".to_string(),
+            buildinfo_base: "https://buildinfos.debian.net/".to_string(),
+            buildinfo_mirror: None,
+            skip_crawl: true,
+            recheck_after: Duration::default(),
+            strict: false,
+            exclude_unsigned: false,
+            concurrency: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn export_from_snapshot() {
+        let db = Database::open(":memory:").unwrap();
+        let client = HttpClient::new(reqwest::Client::new());
+        db.add_buildinfo(
+            "https://buildinfos.debian.net/buildinfo-pool/r/rust-sniffglue/rust-sniffglue_0.14.0-2_amd64.buildinfo".to_string(),
+            BUILDINFO.to_string(),
+            &BUILDINFO.parse().unwrap(),
+        )
+        .unwrap();
+
+        let indexes = vec![PackagesIndex {
+            url: "dists/sid/main/binary-amd64/Packages".to_string(),
+            sha256: db.blobs().add(PACKAGES).unwrap().sha256,
+            cache: None,
+        }];
+        let pending = PendingSnapshot::record(&indexes);
+        let first = run(&db, &client, None, &config(), indexes).await.unwrap();
+        pending.store(&db, &first.groups).unwrap();
+
+        let (_, indexes) = snapshot::open(&db, None).unwrap();
+        // stats counts the same packages without generating build groups
+        assert_eq!(
+            snapshot::coverage(&db, &indexes).unwrap(),
+            snapshot::Coverage {
+                packages: 2,
+                with_buildinfo: 2 - first.report.missing_buildinfo.len(),
+            }
+        );
+        let second = run(&db, &client, None, &config(), indexes).await.unwrap();
+
+        let json = serde_json::to_value(&second.groups).unwrap();
+        assert_eq!(json, serde_json::to_value(&first.groups).unwrap());
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {
+                    "name": "rust-sniffglue",
+                    "version": "0.14.0-2",
+                    "distro": "debian",
+                    "suite": "main",
+                    "architecture": "all",
+                    "input_url": "https://buildinfos.debian.net/missing-buildinfo/rust-sniffglue/0.14.0-2",
+                    "artifacts": [{
+                        "name": "sniffglue-doc",
+                        "version": "0.14.0-2",
+                        "url": "https://deb.debian.org/debian/pool/main/r/rust-sniffglue/sniffglue-doc_0.14.0-2_all.deb",
+                    }],
+                },
+                {
+                    "name": "rust-sniffglue",
+                    "version": "0.14.0-2",
+                    "distro": "debian",
+                    "suite": "main",
+                    "architecture": "amd64",
+                    "input_url": "https://buildinfos.debian.net/buildinfo-pool/r/rust-sniffglue/rust-sniffglue_0.14.0-2_amd64.buildinfo",
+                    "artifacts": [{
+                        "name": "sniffglue",
+                        "version": "0.14.0-2",
+                        "url": "https://deb.debian.org/debian/pool/main/r/rust-sniffglue/sniffglue_0.14.0-2_amd64.deb",
+                    }],
+                },
+            ])
+        );
+        assert_eq!(second.report.missing_buildinfo.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn crawl_local_mirror() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("buildinfo-pool/r/rust-sniffglue");
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(
+            folder.join("rust-sniffglue_0.14.0-2_amd64.buildinfo"),
+            BUILDINFO,
+        )
+        .unwrap();
+
+        let db = Database::open(":memory:").unwrap();
+        let client = HttpClient::new(reqwest::Client::new());
+        let config = Config {
+            buildinfo_mirror: Some(dir.path().to_str().unwrap().to_string()),
+            skip_crawl: false,
+            ..config()
+        };
+        let indexes = vec![PackagesIndex {
+            url: "dists/sid/main/binary-amd64/Packages".to_string(),
+            sha256: db.blobs().add(PACKAGES).unwrap().sha256,
+            cache: None,
+        }];
+        let out = run(&db, &client, None, &config, indexes).await.unwrap();
+
+        let input_urls = out
+            .groups
+            .iter()
+            .map(|group| group.input_url.as_deref().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            input_urls,
+            &[
+                "https://buildinfos.debian.net/missing-buildinfo/rust-sniffglue/0.14.0-2",
+                "https://buildinfos.debian.net/buildinfo-pool/r/rust-sniffglue/rust-sniffglue_0.14.0-2_amd64.buildinfo",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn report_unsigned_buildinfos() {
+        let db = Database::open(":memory:").unwrap();
+        let client = HttpClient::new(reqwest::Client::new());
+        let url = "https://buildinfos.debian.net/buildinfo-pool/r/rust-sniffglue/rust-sniffglue_0.14.0-2_amd64.buildinfo";
+        db.add_buildinfo(
+            url.to_string(),
+            BUILDINFO.to_string(),
+            &BUILDINFO.parse().unwrap(),
+        )
+        .unwrap();
+        // the buildinfo file isn't signed, gpgv never looks at the keyring
+        let keyring_file = tempfile::NamedTempFile::new().unwrap();
+        let keyring = Keyring::open(keyring_file.path().to_str().unwrap()).unwrap();
+        let indexes = || {
+            vec![PackagesIndex {
+                url: "dists/sid/main/binary-amd64/Packages".to_string(),
+                sha256: db.blobs().add(PACKAGES).unwrap().sha256,
+                cache: None,
+            }]
+        };
+
+        // reported, but still used
+        let out = run(&db, &client, Some(&keyring), &config(), indexes())
+            .await
+            .unwrap();
+        assert_eq!(
+            out.report.unsigned_buildinfos,
+            &[FailedUrl {
+                url: url.to_string(),
+                reason: "Not signed".to_string(),
+            }]
+        );
+        assert!(out
+            .groups
+            .iter()
+            .any(|group| group.input_url.as_deref() == Some(url)));
+
+        // the stored result is used in the next run
+        let config = Config {
+            exclude_unsigned: true,
+            ..config()
+        };
+        let out = run(&db, &client, Some(&keyring), &config, indexes())
+            .await
+            .unwrap();
+        assert_eq!(out.report.unsigned_buildinfos.len(), 1);
+        assert!(out
+            .groups
+            .iter()
+            .all(|group| group.input_url.as_deref() != Some(url)));
+        assert!(out
+            .report
+            .missing_buildinfo
+            .iter()
+            .any(|pkg| pkg.reason == MissingReason::Unsigned));
+    }
+
+    #[tokio::test]
+    async fn stored_buildinfo_fails_to_parse() {
+        let db = Database::open(":memory:").unwrap();
+        let client = HttpClient::new(reqwest::Client::new());
+        let url = "https://buildinfos.debian.net/buildinfo-pool/r/rust-sniffglue/rust-sniffglue_0.14.0-2_amd64.buildinfo";
+        // accepted by an older version that didn't reject duplicate fields
+        db.add_buildinfo(
+            url.to_string(),
+            format!("{}Source: rust-sniffglue\n", BUILDINFO),
+            &BUILDINFO.parse().unwrap(),
+        )
+        .unwrap();
+        let indexes = || {
+            vec![PackagesIndex {
+                url: "dists/sid/main/binary-amd64/Packages".to_string(),
+                sha256: db.blobs().add(PACKAGES).unwrap().sha256,
+                cache: None,
+            }]
+        };
+
+        let out = run(&db, &client, None, &config(), indexes()).await.unwrap();
+        assert_eq!(out.report.failed_parses.len(), 1);
+        assert_eq!(out.report.failed_parses[0].url, url);
+        assert_eq!(out.report.failed_parses[0].kind, "duplicate-field");
+        assert!(out
+            .report
+            .missing_buildinfo
+            .iter()
+            .any(|pkg| pkg.reason == MissingReason::ParseFailed));
+        assert!(out
+            .groups
+            .iter()
+            .all(|group| group.input_url.as_deref() != Some(url)));
+
+        let config = Config {
+            strict: true,
+            ..config()
+        };
+        assert!(run(&db, &client, None, &config, indexes()).await.is_err());
+    }
+}
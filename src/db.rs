@@ -1,17 +1,78 @@
+use crate::blobs::BlobStore;
 use crate::buildinfo;
 use crate::errors::*;
+use crate::html::ListingEntry;
 use crate::migrations;
 use crate::models::artifact::{Artifact, NewArtifact};
 use crate::models::buildinfo::{Buildinfo, NewBuildinfo};
+use crate::models::http_cache::{HttpCacheEntry, NewHttpCacheEntry};
+use crate::models::missing_buildinfo::{MissingBuildinfo, NewMissingBuildinfo};
+use crate::models::parse_failure::{NewParseFailure, ParseFailure};
+use crate::models::release::NewRelease;
+use crate::models::snapshot::{NewSnapshotIndex, Snapshot, SnapshotIndex};
+use crate::release;
 use crate::schema::*;
+use crate::version;
+use chrono::{NaiveDateTime, Utc};
 use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 
+// Unreferenced blobs are only removed after this time, a running crawl may not have stored them yet
+const BLOB_GRACE_PERIOD: Duration = Duration::from_secs(3600);
+
+/// The sqlite database and the files stored next to it
 pub struct Database {
     sqlite: diesel::SqliteConnection,
+    blobs: BlobStore,
+}
+
+/// Number of rows in the database, see `Database::stats`
+#[derive(Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Buildinfo files
+    pub buildinfos: i64,
+    /// Buildinfo files with a valid signature
+    pub signed_buildinfos: i64,
+    /// Artifacts listed in the buildinfo files
+    pub artifacts: i64,
+    /// Cached http responses
+    pub http_cache_entries: i64,
+    /// Files in the blob store
+    pub blobs: u64,
+    /// Total size of the files in the blob store
+    pub blob_bytes: u64,
+    /// Packages that are known to have no buildinfo file
+    pub missing_buildinfos: i64,
+    /// Buildinfo files that failed to parse
+    pub parse_failures: i64,
+    /// Release files
+    pub releases: i64,
+    /// Snapshots of Packages indexes
+    pub snapshots: i64,
 }
 
+/// Number of rows removed by `Database::gc`
+#[derive(Debug, Default, PartialEq)]
+pub struct GcStats {
+    /// Packages without buildinfo that weren't checked recently
+    pub missing_buildinfos: usize,
+    /// Parse failures that weren't seen recently
+    pub parse_failures: usize,
+    /// Snapshots beyond the number that is kept
+    pub snapshots: usize,
+    /// Unreferenced files in the blob store
+    pub blobs: usize,
+}
+
+/// Version of the migration that added checksums to artifacts
+const ARTIFACT_CHECKSUMS_MIGRATION: &str = "20261018152000";
+
 impl Database {
+    /// Open or create the database and run missing migrations, use `:memory:` for a temporary one
     pub fn open(path: &str) -> Result<Database> {
         let sqlite = SqliteConnection::establish(path).context("Failed to connect to database")?;
 
@@ -30,30 +91,145 @@ impl Database {
         ")?;
 
         debug!("Running missing migrations (if any)");
-        migrations::run(&sqlite).context("Failed to run migrations")?;
+        let applied = migrations::run(&sqlite).context("Failed to run migrations")?;
+
+        // large files are stored next to the database, e.g. `foo.db-blobs/`
+        let blobs = if path == ":memory:" {
+            BlobStore::temporary()?
+        } else {
+            BlobStore::new(PathBuf::from(format!("{}-blobs", path)))?
+        };
+
+        let db = Database { sqlite, blobs };
+        // artifacts that were added before checksums were tracked are only updated once, if the
+        // stored buildinfo has no checksum for them it's not going to have one next time either
+        if applied.contains(ARTIFACT_CHECKSUMS_MIGRATION) {
+            db.backfill_artifact_checksums()?;
+        }
+        Ok(db)
+    }
 
-        Ok(Database { sqlite })
+    pub(crate) fn blobs(&self) -> &BlobStore {
+        &self.blobs
     }
 
-    pub fn buildinfo_for_artifact_filename(&self, my_filename: &str) -> Result<Option<Buildinfo>> {
+    /// Find the buildinfo for an artifact, if the sha256 is known it also needs to match.
+    ///
+    /// If multiple buildinfo files list the artifact, the one with the given version is preferred.
+    pub fn buildinfo_for_artifact(
+        &self,
+        my_filename: &str,
+        my_sha256: Option<&str>,
+        my_version: &str,
+    ) -> Result<Option<Buildinfo>> {
+        let buildinfo_ids = {
+            use crate::schema::artifacts::dsl::*;
+            let mut query = artifacts.filter(file_name.eq(my_filename)).into_boxed();
+            if let Some(my_sha256) = my_sha256 {
+                query = query.filter(sha256.eq(my_sha256));
+            }
+            query.select(buildinfo_id).load::<i32>(&self.sqlite)?
+        };
+
+        let candidates = {
+            use crate::schema::buildinfos::dsl::*;
+            buildinfos
+                .filter(id.eq_any(buildinfo_ids))
+                .order(id.asc())
+                .load::<Buildinfo>(&self.sqlite)?
+        };
+        if candidates.len() <= 1 {
+            return Ok(candidates.into_iter().next());
+        }
+
+        debug!(
+            "Found {} buildinfo files for {:?}, looking for version {:?}",
+            candidates.len(),
+            my_filename,
+            my_version
+        );
+        let position = candidates.iter().position(|row| {
+            row.content
+                .parse::<buildinfo::Buildinfo>()
+                .map(|b| version::compare(&b.version, my_version) == Ordering::Equal)
+                .unwrap_or(false)
+        });
+        Ok(candidates.into_iter().nth(position.unwrap_or(0)))
+    }
+
+    /// All buildinfo files that list an artifact with this filename, regardless of the checksum
+    pub fn artifact_candidates(&self, my_filename: &str) -> Result<Vec<(Artifact, Buildinfo)>> {
+        use crate::schema::artifacts::dsl::*;
+        let candidates = artifacts
+            .inner_join(buildinfos::table)
+            .filter(file_name.eq(my_filename))
+            .order(buildinfos::id.asc())
+            .load::<(Artifact, Buildinfo)>(&self.sqlite)?;
+        Ok(candidates)
+    }
+
+    /// At least one buildinfo file lists an artifact with this filename
+    pub fn artifact_filename_known(&self, my_filename: &str) -> Result<bool> {
         use crate::schema::artifacts::dsl::*;
         let artifact = artifacts
             .filter(file_name.eq(my_filename))
-            .first::<Artifact>(&self.sqlite)
+            .select(id)
+            .first::<i32>(&self.sqlite)
             .optional()?;
+        Ok(artifact.is_some())
+    }
 
-        if let Some(artifact) = artifact {
-            use crate::schema::buildinfos::dsl::*;
-            let buildinfo = buildinfos
-                .filter(id.eq(artifact.buildinfo_id))
-                .first(&self.sqlite)
-                .optional()?;
-            Ok(buildinfo)
-        } else {
-            Ok(None)
+    // Artifacts that were added before checksums were tracked get them from the stored buildinfo
+    fn backfill_artifact_checksums(&self) -> Result<()> {
+        let buildinfo_ids = {
+            use crate::schema::artifacts::dsl::*;
+            artifacts
+                .filter(sha256.is_null())
+                .select(buildinfo_id)
+                .distinct()
+                .load::<i32>(&self.sqlite)?
+        };
+        if buildinfo_ids.is_empty() {
+            return Ok(());
         }
+
+        info!(
+            "Adding missing artifact checksums for {} buildinfo files",
+            buildinfo_ids.len()
+        );
+        self.sqlite.transaction::<_, Error, _>(|| {
+            for my_buildinfo_id in buildinfo_ids {
+                let row = {
+                    use crate::schema::buildinfos::dsl::*;
+                    buildinfos
+                        .filter(id.eq(my_buildinfo_id))
+                        .first::<Buildinfo>(&self.sqlite)?
+                };
+
+                let buildinfo = match row.content.parse::<buildinfo::Buildinfo>() {
+                    Ok(buildinfo) => buildinfo,
+                    Err(err) => {
+                        warn!("Failed to parse buildinfo file {:?}: {:#}", row.url, err);
+                        continue;
+                    }
+                };
+
+                use crate::schema::artifacts::dsl::*;
+                for checksum in buildinfo.checksums_sha256 {
+                    diesel::update(
+                        artifacts
+                            .filter(buildinfo_id.eq(my_buildinfo_id))
+                            .filter(file_name.eq(&checksum.file_name)),
+                    )
+                    .set((sha256.eq(&checksum.hash), size.eq(checksum.size as i64)))
+                    .execute(&self.sqlite)?;
+                }
+            }
+            Ok(())
+        })
     }
 
+    /// The buildinfo file downloaded from this url, if it's in the database
     pub fn buildinfo_url_cache(&self, my_url: &str) -> Result<Option<Buildinfo>> {
         use crate::schema::buildinfos::dsl::*;
         let buildinfo = buildinfos
@@ -63,47 +239,479 @@ impl Database {
         Ok(buildinfo)
     }
 
-    pub fn add_buildinfo(&self, url: String, content: String) -> Result<Vec<String>> {
+    /// Remember the result of a signature check, `my_signer` is `None` if the signature isn't valid
+    pub fn set_buildinfo_verification(
+        &self,
+        my_id: i32,
+        my_signer: Option<&str>,
+        keyring_id: &str,
+    ) -> Result<()> {
+        use crate::schema::buildinfos::dsl::*;
+        diesel::update(buildinfos.filter(id.eq(my_id)))
+            .set((signer.eq(my_signer), verified_with.eq(keyring_id)))
+            .execute(&self.sqlite)?;
+        Ok(())
+    }
+
+    /// Store a buildinfo file and its artifacts, returns the file names of the artifacts
+    pub fn add_buildinfo(
+        &self,
+        url: String,
+        content: String,
+        buildinfo: &buildinfo::Buildinfo,
+    ) -> Result<Vec<String>> {
         let mut out = Vec::new();
 
-        match content.parse::<buildinfo::Buildinfo>() {
-            Ok(buildinfo) => {
-                self.sqlite.transaction::<_, Error, _>(|| {
-                    let my_url = url.clone();
+        self.sqlite.transaction::<_, Error, _>(|| {
+            let my_url = url.clone();
+
+            // insert buildinfo
+            diesel::insert_into(buildinfos::table)
+                .values(NewBuildinfo { url, content })
+                .execute(&self.sqlite)?;
 
-                    // insert buildinfo
-                    diesel::insert_into(buildinfos::table)
-                        .values(NewBuildinfo { url, content })
+            // get buildinfo row id
+            let buildinfo_id = {
+                use crate::schema::buildinfos::dsl::*;
+                let buildinfo_row = buildinfos
+                    .filter(url.eq(&my_url))
+                    .first::<Buildinfo>(&self.sqlite)?;
+                buildinfo_row.id
+            };
+
+            if !buildinfo.architecture.contains("source") {
+                // insert artifacts too
+                for artifact in &buildinfo.artifacts {
+                    out.push(artifact.to_string());
+                    let checksum = buildinfo.sha256(artifact);
+                    diesel::insert_into(artifacts::table)
+                        .values(NewArtifact {
+                            file_name: artifact.to_string(),
+                            buildinfo_id,
+                            sha256: checksum.map(|c| c.hash.clone()),
+                            size: checksum.map(|c| c.size as i64),
+                        })
                         .execute(&self.sqlite)?;
+                }
+            }
 
-                    // get buildinfo row id
-                    let buildinfo_id = {
-                        use crate::schema::buildinfos::dsl::*;
-                        let buildinfo_row = buildinfos
-                            .filter(url.eq(&my_url))
-                            .first::<Buildinfo>(&self.sqlite)?;
-                        buildinfo_row.id
-                    };
-
-                    if !buildinfo.architecture.contains("source") {
-                        // insert artifacts too
-                        for artifact in buildinfo.artifacts {
-                            out.push(artifact.to_string());
-                            diesel::insert_into(artifacts::table)
-                                .values(NewArtifact {
-                                    file_name: artifact,
-                                    buildinfo_id,
-                                })
-                                .execute(&self.sqlite)?;
-                        }
-                    }
+            // the file may have failed to parse with an older version
+            {
+                use crate::schema::parse_failures::dsl::*;
+                diesel::delete(parse_failures.filter(url.eq(&my_url))).execute(&self.sqlite)?;
+            }
+
+            Ok(())
+        })?;
+
+        Ok(out)
+    }
+
+    /// Remember that a buildinfo file failed to parse, along with its directory listing entry if
+    /// it was just downloaded
+    pub fn add_parse_failure(
+        &self,
+        url: &str,
+        entry: Option<&ListingEntry>,
+        err: &buildinfo::ParseError,
+    ) -> Result<()> {
+        diesel::replace_into(parse_failures::table)
+            .values(NewParseFailure {
+                url: url.to_string(),
+                kind: err.kind().to_string(),
+                line: err.line().map(|line| line as i32),
+                message: err.to_string(),
+                failed_at: Utc::now().naive_utc(),
+                listing_mtime: entry.and_then(|entry| entry.mtime),
+                listing_size: entry.and_then(|entry| entry.size).map(|size| size as i64),
+            })
+            .execute(&self.sqlite)?;
+        Ok(())
+    }
+
+    /// The last parse failure of the buildinfo file at this url
+    pub fn parse_failure(&self, my_url: &str) -> Result<Option<ParseFailure>> {
+        use crate::schema::parse_failures::dsl::*;
+        let failure = parse_failures
+            .filter(url.eq(my_url))
+            .first(&self.sqlite)
+            .optional()?;
+        Ok(failure)
+    }
+
+    /// Buildinfo files that failed to parse and haven't been fixed since
+    pub fn parse_failures(&self) -> Result<Vec<ParseFailure>> {
+        use crate::schema::parse_failures::dsl::*;
+        let failures = parse_failures.order_by(url).load(&self.sqlite)?;
+        Ok(failures)
+    }
+
+    /// The cached response for this url
+    pub fn http_cache(&self, my_url: &str) -> Result<Option<HttpCacheEntry>> {
+        use crate::schema::http_cache::dsl::*;
+        let entry = http_cache
+            .filter(url.eq(my_url))
+            .first(&self.sqlite)
+            .optional()?;
+        Ok(entry)
+    }
+
+    /// Store a response, replacing the previous one for the url
+    pub fn set_http_cache(&self, entry: NewHttpCacheEntry) -> Result<()> {
+        diesel::replace_into(http_cache::table)
+            .values(entry)
+            .execute(&self.sqlite)?;
+        Ok(())
+    }
+
+    /// The result of an earlier crawl that found no buildinfo file for the package
+    pub fn missing_buildinfo(
+        &self,
+        my_deb_folder: &str,
+        my_file_name: &str,
+    ) -> Result<Option<MissingBuildinfo>> {
+        use crate::schema::missing_buildinfos::dsl::*;
+        let entry = missing_buildinfos
+            .filter(deb_folder.eq(my_deb_folder))
+            .filter(file_name.eq(my_file_name))
+            .first(&self.sqlite)
+            .optional()?;
+        Ok(entry)
+    }
+
+    /// Remember that no buildinfo in this listing references the package
+    pub fn set_missing_buildinfo(&self, entry: NewMissingBuildinfo) -> Result<()> {
+        diesel::replace_into(missing_buildinfos::table)
+            .values(entry)
+            .execute(&self.sqlite)?;
+        Ok(())
+    }
+
+    /// Forget that a package has no buildinfo file
+    pub fn remove_missing_buildinfo(&self, my_deb_folder: &str, my_file_name: &str) -> Result<()> {
+        use crate::schema::missing_buildinfos::dsl::*;
+        diesel::delete(
+            missing_buildinfos
+                .filter(deb_folder.eq(my_deb_folder))
+                .filter(file_name.eq(my_file_name)),
+        )
+        .execute(&self.sqlite)?;
+        Ok(())
+    }
+
+    /// Count the rows of all tables and the files in the blob store
+    pub fn stats(&self) -> Result<Stats> {
+        let (blobs, blob_bytes) = self.blobs.usage()?;
+        Ok(Stats {
+            buildinfos: buildinfos::table.count().get_result(&self.sqlite)?,
+            signed_buildinfos: buildinfos::table
+                .filter(buildinfos::signer.is_not_null())
+                .count()
+                .get_result(&self.sqlite)?,
+            artifacts: artifacts::table.count().get_result(&self.sqlite)?,
+            http_cache_entries: http_cache::table.count().get_result(&self.sqlite)?,
+            blobs,
+            blob_bytes,
+            missing_buildinfos: missing_buildinfos::table.count().get_result(&self.sqlite)?,
+            parse_failures: parse_failures::table.count().get_result(&self.sqlite)?,
+            releases: releases::table.count().get_result(&self.sqlite)?,
+            snapshots: snapshots::table.count().get_result(&self.sqlite)?,
+        })
+    }
 
-                    Ok(())
-                })?;
+    /// Forget packages without buildinfo and parse failures that weren't seen since `before`,
+    /// they are most likely gone from the archive. Only the newest `keep_snapshots` snapshots are
+    /// kept, files in the blob store that are no longer referenced are removed. Afterwards the
+    /// database file is compacted.
+    pub fn gc(&self, before: NaiveDateTime, keep_snapshots: i64) -> Result<GcStats> {
+        let stats = self.sqlite.transaction::<_, Error, _>(|| {
+            let missing_buildinfos = diesel::delete(
+                missing_buildinfos::table.filter(missing_buildinfos::checked_at.lt(before)),
+            )
+            .execute(&self.sqlite)?;
+            let parse_failures =
+                diesel::delete(parse_failures::table.filter(parse_failures::failed_at.lt(before)))
+                    .execute(&self.sqlite)?;
+
+            let keep = snapshots::table
+                .select(snapshots::id)
+                .order(snapshots::id.desc())
+                .limit(keep_snapshots)
+                .load::<i32>(&self.sqlite)?;
+            let snapshots = diesel::delete(snapshots::table.filter(snapshots::id.ne_all(keep)))
+                .execute(&self.sqlite)?;
+
+            Ok(GcStats {
+                missing_buildinfos,
+                parse_failures,
+                snapshots,
+                blobs: 0,
+            })
+        })?;
+
+        let mut keep = http_cache::table
+            .select(http_cache::blob)
+            .filter(http_cache::blob.is_not_null())
+            .load::<Option<String>>(&self.sqlite)?
+            .into_iter()
+            .flatten()
+            .collect::<HashSet<_>>();
+        keep.extend(
+            snapshot_indexes::table
+                .select(snapshot_indexes::blob)
+                .load::<String>(&self.sqlite)?,
+        );
+        keep.extend(
+            snapshots::table
+                .select(snapshots::build_groups)
+                .load::<Option<String>>(&self.sqlite)?
+                .into_iter()
+                .flatten(),
+        );
+        let stats = GcStats {
+            blobs: self
+                .blobs
+                .retain(&keep, SystemTime::now() - BLOB_GRACE_PERIOD)?,
+            ..stats
+        };
+
+        debug!("Compacting database");
+        self.sqlite.batch_execute("VACUUM")?;
+        Ok(stats)
+    }
+
+    /// Store the urls and blobs of the Packages indexes of a crawl and the blob of the build
+    /// groups that were generated from them, returns the id of the new snapshot
+    pub fn add_snapshot(
+        &self,
+        indexes: Vec<(String, String)>,
+        build_groups: String,
+    ) -> Result<i32> {
+        self.sqlite.transaction::<_, Error, _>(|| {
+            diesel::insert_into(snapshots::table)
+                .values(snapshots::build_groups.eq(build_groups))
+                .execute(&self.sqlite)?;
+
+            let my_snapshot_id = snapshots::table
+                .select(snapshots::id)
+                .order(snapshots::id.desc())
+                .first::<i32>(&self.sqlite)?;
+
+            for (url, blob) in indexes {
+                diesel::insert_into(snapshot_indexes::table)
+                    .values(NewSnapshotIndex {
+                        snapshot_id: my_snapshot_id,
+                        url,
+                        blob,
+                    })
+                    .execute(&self.sqlite)?;
             }
-            Err(err) => warn!("Failed to parse buildinfo file: {:#}", err),
+
+            Ok(my_snapshot_id)
+        })
+    }
+
+    /// Get a snapshot by id, or the newest one if no id is given
+    pub fn snapshot(&self, my_id: Option<i32>) -> Result<Option<Snapshot>> {
+        use crate::schema::snapshots::dsl::*;
+        let mut query = snapshots.order(id.desc()).into_boxed();
+        if let Some(my_id) = my_id {
+            query = query.filter(id.eq(my_id));
         }
+        let snapshot = query.first(&self.sqlite).optional()?;
+        Ok(snapshot)
+    }
 
-        Ok(out)
+    /// The Packages indexes of a snapshot
+    pub fn snapshot_indexes(&self, my_snapshot_id: i32) -> Result<Vec<SnapshotIndex>> {
+        use crate::schema::snapshot_indexes::dsl::*;
+        let indexes = snapshot_indexes
+            .filter(snapshot_id.eq(my_snapshot_id))
+            .order(id.asc())
+            .load(&self.sqlite)?;
+        Ok(indexes)
+    }
+
+    /// Store a Release file that was read during a crawl
+    pub fn add_release(&self, url: String, release: &release::Release) -> Result<()> {
+        diesel::insert_into(releases::table)
+            .values(NewRelease {
+                url,
+                origin: release.origin.clone(),
+                label: release.label.clone(),
+                suite: release.suite.clone(),
+                codename: release.codename.clone(),
+                date: release.date.clone(),
+            })
+            .execute(&self.sqlite)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUILDINFO: &str = "Format: 1.0
+Source: rust-sniffglue
+Binary: sniffglue
+Architecture: amd64
+Version: 0.14.0-2
+Checksums-Sha256:
+ 448c781a9e594227bc9f0d6c65b8beba2b3add68d3583020de188d4cfa365b40 732980 sniffglue_0.14.0-2_amd64.deb
+Build-Origin: Debian
+";
+
+    #[test]
+    fn lookup_artifact_by_checksum() {
+        let db = Database::open(":memory:").unwrap();
+        let url = "https://buildinfos.debian.net/buildinfo-pool/r/rust-sniffglue/rust-sniffglue_0.14.0-2_amd64.buildinfo";
+        let artifacts = db
+            .add_buildinfo(
+                url.to_string(),
+                BUILDINFO.to_string(),
+                &BUILDINFO.parse().unwrap(),
+            )
+            .unwrap();
+        assert_eq!(artifacts, &["sniffglue_0.14.0-2_amd64.deb"]);
+
+        let buildinfo = db
+            .buildinfo_for_artifact("sniffglue_0.14.0-2_amd64.deb", None, "0.14.0-2")
+            .unwrap()
+            .unwrap();
+        assert_eq!(buildinfo.url, url);
+
+        let buildinfo = db
+            .buildinfo_for_artifact(
+                "sniffglue_0.14.0-2_amd64.deb",
+                Some("448c781a9e594227bc9f0d6c65b8beba2b3add68d3583020de188d4cfa365b40"),
+                "0.14.0-2",
+            )
+            .unwrap();
+        assert!(buildinfo.is_some());
+
+        let buildinfo = db
+            .buildinfo_for_artifact(
+                "sniffglue_0.14.0-2_amd64.deb",
+                Some("0000000000000000000000000000000000000000000000000000000000000000"),
+                "0.14.0-2",
+            )
+            .unwrap();
+        assert!(buildinfo.is_none());
+        assert!(db
+            .artifact_filename_known("sniffglue_0.14.0-2_amd64.deb")
+            .unwrap());
+
+        let candidates = db
+            .artifact_candidates("sniffglue_0.14.0-2_amd64.deb")
+            .unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].1.url, url);
+    }
+
+    #[test]
+    fn migrations_are_only_applied_once() {
+        let sqlite = SqliteConnection::establish(":memory:").unwrap();
+        let applied = migrations::run(&sqlite).unwrap();
+        assert!(applied.contains(ARTIFACT_CHECKSUMS_MIGRATION));
+        assert!(migrations::run(&sqlite).unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_buildinfo_is_replaced() {
+        let db = Database::open(":memory:").unwrap();
+        let entry = |listing_hash: &str| NewMissingBuildinfo {
+            deb_folder: "r/rust-sniffglue".to_string(),
+            file_name: "sniffglue_0.14.0-2_amd64.deb".to_string(),
+            listing_hash: listing_hash.to_string(),
+            checked_at: chrono::Utc::now().naive_utc(),
+        };
+        db.set_missing_buildinfo(entry("a")).unwrap();
+        db.set_missing_buildinfo(entry("b")).unwrap();
+
+        let missing = db
+            .missing_buildinfo("r/rust-sniffglue", "sniffglue_0.14.0-2_amd64.deb")
+            .unwrap()
+            .unwrap();
+        assert_eq!(missing.listing_hash, "b");
+
+        db.remove_missing_buildinfo("r/rust-sniffglue", "sniffglue_0.14.0-2_amd64.deb")
+            .unwrap();
+        let missing = db
+            .missing_buildinfo("r/rust-sniffglue", "sniffglue_0.14.0-2_amd64.deb")
+            .unwrap();
+        assert!(missing.is_none());
+    }
+
+    #[test]
+    fn gc_removes_old_entries() {
+        let db = Database::open(":memory:").unwrap();
+        let now = chrono::Utc::now().naive_utc();
+        let entry = |file_name: &str, checked_at| NewMissingBuildinfo {
+            deb_folder: "r/rust-sniffglue".to_string(),
+            file_name: file_name.to_string(),
+            listing_hash: "a".to_string(),
+            checked_at,
+        };
+        db.set_missing_buildinfo(entry("old.deb", now - chrono::Duration::days(60)))
+            .unwrap();
+        db.set_missing_buildinfo(entry("new.deb", now)).unwrap();
+
+        let blob = db.blobs().add(b"Package: a\n").unwrap();
+        let groups = db.blobs().add(b"[]").unwrap();
+        for _ in 0..3 {
+            db.add_snapshot(
+                vec![("Packages".to_string(), blob.sha256.clone())],
+                groups.sha256.clone(),
+            )
+            .unwrap();
+        }
+
+        let stats = db.gc(now - chrono::Duration::days(30), 2).unwrap();
+        assert_eq!(
+            stats,
+            GcStats {
+                missing_buildinfos: 1,
+                parse_failures: 0,
+                snapshots: 1,
+                blobs: 0,
+            }
+        );
+        assert_eq!(db.snapshot(Some(1)).unwrap(), None);
+        let latest = db.snapshot(None).unwrap().unwrap();
+        assert_eq!(latest.id, 3);
+        assert_eq!(db.snapshot_indexes(latest.id).unwrap().len(), 1);
+        assert!(db
+            .missing_buildinfo("r/rust-sniffglue", "old.deb")
+            .unwrap()
+            .is_none());
+        assert_eq!(db.stats().unwrap().missing_buildinfos, 1);
+    }
+
+    #[test]
+    fn lookup_artifact_prefers_version() {
+        let db = Database::open(":memory:").unwrap();
+        let binnmu = BUILDINFO.replace("Version: 0.14.0-2\n", "Version: 0.14.0-2+b1\n");
+        db.add_buildinfo(
+            "rebuild.buildinfo".to_string(),
+            BUILDINFO.to_string(),
+            &BUILDINFO.parse().unwrap(),
+        )
+        .unwrap();
+        let parsed = binnmu.parse().unwrap();
+        db.add_buildinfo("binnmu.buildinfo".to_string(), binnmu, &parsed)
+            .unwrap();
+
+        let buildinfo = db
+            .buildinfo_for_artifact("sniffglue_0.14.0-2_amd64.deb", None, "0.14.0-2+b1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(buildinfo.url, "binnmu.buildinfo");
+
+        let buildinfo = db
+            .buildinfo_for_artifact("sniffglue_0.14.0-2_amd64.deb", None, "0.14.0-2")
+            .unwrap()
+            .unwrap();
+        assert_eq!(buildinfo.url, "rebuild.buildinfo");
     }
 }
@@ -1,22 +1,86 @@
+use crate::compression::Compression;
 use crate::errors::*;
+use crate::http_cache::PendingCache;
+use crate::version::Version;
 use std::io::prelude::*;
 use std::io::BufReader;
-use xz2::read::XzDecoder;
 
-#[derive(Debug, PartialEq)]
+/// The source package a binary package was built from
+#[derive(Debug, PartialEq, Default)]
 pub struct DebianSource {
+    /// The name of the source package
     pub name: String,
+    /// The version, only present if it differs from the version of the binary package
     pub version: Option<String>,
 }
 
-#[derive(Debug, PartialEq)]
+/// A binary package from a Packages index
+#[derive(Debug, PartialEq, Default)]
 pub struct DebianBinaryPkg {
+    /// The name of the binary package
     pub name: String,
+    /// The source package, the name of the binary package if there's no `Source` field
     pub source: DebianSource,
+    /// The version of the binary package, including epoch and binNMU suffix
     pub version: String,
+    /// The architecture, e.g. `amd64` or `all`
     pub architecture: String,
+    /// The file name of the .deb, e.g. `sniffglue_0.14.0-2_amd64.deb`
     pub file_name: String,
+    /// The folder of the source package in the pool, e.g. `r/rust-sniffglue`
     pub deb_folder: String,
+    /// The path of the .deb relative to the mirror, from the `Filename` field
+    pub path: String,
+    /// The size of the .deb in bytes
+    pub size: Option<u64>,
+    /// The `Installed-Size` field, in KiB
+    pub installed_size: Option<u64>,
+    /// The sha256 of the .deb
+    pub sha256: Option<String>,
+    /// The md5 of the .deb
+    pub md5sum: Option<String>,
+    /// Source packages that were built into this package, from `Built-Using`
+    pub built_using: Vec<DebianSource>,
+    /// The `Multi-Arch` field, e.g. `same` or `foreign`
+    pub multi_arch: Option<String>,
+    /// The section, e.g. `net`
+    pub section: Option<String>,
+    /// The priority, e.g. `optional`
+    pub priority: Option<String>,
+    /// The full description, continuation lines are kept as-is (including their leading space)
+    pub description: Option<String>,
+}
+
+impl DebianBinaryPkg {
+    /// The url of the .deb on the given mirror
+    pub fn url(&self, source: &str) -> String {
+        format!("{}/{}", source.trim_end_matches('/'), self.path)
+    }
+
+    /// The version of the source package, either from `Source: name (ver)` or derived from the binary version
+    pub fn source_version(&self) -> String {
+        if let Some(version) = &self.source.version {
+            return version.clone();
+        }
+        match self.version.parse::<Version>() {
+            Ok(version) => version.without_binnmu().to_string(),
+            Err(_) => self.version.clone(),
+        }
+    }
+
+    /// The `Version:` of the buildinfo file that produced this package, the source version with the binNMU suffix
+    pub fn buildinfo_version(&self) -> String {
+        let source_version = self.source_version();
+        match self
+            .version
+            .parse::<Version>()
+            .ok()
+            .and_then(|v| v.binnmu())
+        {
+            Some(binnmu) => format!("{}+b{}", source_version, binnmu),
+            None => source_version,
+        }
+    }
 }
 
 // Builder for DebianBinaryPkg
@@ -28,6 +92,16 @@ struct DraftBinaryPkg {
     architecture: Option<String>,
     file_name: Option<String>,
     deb_folder: Option<String>,
+    path: Option<String>,
+    size: Option<u64>,
+    installed_size: Option<u64>,
+    sha256: Option<String>,
+    md5sum: Option<String>,
+    built_using: Vec<DebianSource>,
+    multi_arch: Option<String>,
+    section: Option<String>,
+    priority: Option<String>,
+    description: Option<String>,
 }
 
 impl TryInto<DebianBinaryPkg> for DraftBinaryPkg {
@@ -54,87 +128,381 @@ impl TryInto<DebianBinaryPkg> for DraftBinaryPkg {
             deb_folder: self
                 .deb_folder
                 .context("Package is missing `Filename` field")?,
+            path: self.path.context("Package is missing `Filename` field")?,
+            size: self.size,
+            installed_size: self.installed_size,
+            sha256: self.sha256,
+            md5sum: self.md5sum,
+            built_using: self.built_using,
+            multi_arch: self.multi_arch,
+            section: self.section,
+            priority: self.priority,
+            description: self.description,
         };
         Ok(bin)
     }
 }
 
-pub fn parse_compressed_packages_db(packages_db: &[u8]) -> Result<Vec<DebianBinaryPkg>> {
-    let reader = XzDecoder::new(packages_db);
-    parse_packages_db(reader)
+impl DraftBinaryPkg {
+    fn add_field(&mut self, key: &str, value: &str) -> Result<()> {
+        trace!("Parsed line in package database: {key:?} => {value:?}");
+        match key {
+            "Package" => {
+                trace!("Found field (package): {value:?}");
+                self.name = Some(value.to_string());
+            }
+            "Source" => {
+                trace!("Found field (source): {value:?}");
+                let (name, version) = value
+                    .rsplit_once(' ')
+                    .map(|(name, version)| {
+                        let version = version.trim_matches(|c| c == '(' || c == ')').to_string();
+                        (name.to_string(), Some(version))
+                    })
+                    .unwrap_or_else(|| (value.to_string(), None));
+
+                let s = DebianSource {
+                    name: name.to_string(),
+                    version,
+                };
+                trace!("Source reference: {s:?}");
+                self.source = Some(s);
+            }
+            "Version" => {
+                trace!("Found field (version): {value:?}");
+                self.version = Some(value.to_string());
+            }
+            "Architecture" => {
+                trace!("Found field (architecture): {value:?}");
+                self.architecture = Some(value.to_string());
+            }
+            "Filename" => {
+                let (folder, file_name) = value.rsplit_once('/').unwrap_or(("", value));
+                trace!(
+                    "Found value of filename field ({value:?}), resolved to filename {file_name:?}"
+                );
+                self.file_name = Some(file_name.to_string());
+                let (_, folder) = folder.split_once('/').unwrap_or(("", value));
+                let (_, folder) = folder.split_once('/').unwrap_or(("", value));
+                self.deb_folder = Some(folder.to_string());
+                self.path = Some(value.to_string());
+            }
+            "Size" => {
+                let size = value
+                    .parse()
+                    .with_context(|| anyhow!("Invalid `Size` field: {value:?}"))?;
+                self.size = Some(size);
+            }
+            "Installed-Size" => {
+                let size = value
+                    .parse()
+                    .with_context(|| anyhow!("Invalid `Installed-Size` field: {value:?}"))?;
+                self.installed_size = Some(size);
+            }
+            "SHA256" => self.sha256 = Some(value.to_string()),
+            "MD5sum" => self.md5sum = Some(value.to_string()),
+            "Built-Using" => {
+                trace!("Found field (built-using): {value:?}");
+                self.built_using = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(parse_built_using)
+                    .collect();
+            }
+            "Multi-Arch" => self.multi_arch = Some(value.to_string()),
+            "Section" => self.section = Some(value.to_string()),
+            "Priority" => self.priority = Some(value.to_string()),
+            "Description" => self.description = Some(value.to_string()),
+            _ => (),
+        }
+        Ok(())
+    }
 }
 
-pub fn parse_packages_db<R: Read>(reader: R) -> Result<Vec<DebianBinaryPkg>> {
-    let reader = BufReader::new(reader);
-
-    let mut out = Vec::new();
-    let mut draft = DraftBinaryPkg::default();
-
-    for line in reader.lines() {
-        let line = line?;
-
-        if line.is_empty() {
-            let bin = draft.try_into()?;
-            trace!("Learned debian binary pkg: {:?}", bin);
-            out.push(bin);
-            draft = DraftBinaryPkg::default();
-        } else if !line.starts_with(' ') {
-            if let Some((key, value)) = line.split_once(": ") {
-                trace!("Parsed line in package database: {key:?} => {value:?}");
-                match key {
-                    "Package" => {
-                        trace!("Found field (package): {value:?}");
-                        draft.name = Some(value.to_string());
-                    }
-                    "Source" => {
-                        trace!("Found field (source): {value:?}");
-                        let (name, version) = value
-                            .rsplit_once(' ')
-                            .map(|(name, version)| {
-                                let version =
-                                    version.trim_matches(|c| c == '(' || c == ')').to_string();
-                                (name.to_string(), Some(version))
-                            })
-                            .unwrap_or_else(|| (value.to_string(), None));
-
-                        let s = DebianSource {
-                            name: name.to_string(),
-                            version,
-                        };
-                        trace!("Source reference: {s:?}");
-                        draft.source = Some(s);
-                    }
-                    "Version" => {
-                        trace!("Found field (version): {value:?}");
-                        draft.version = Some(value.to_string());
-                    }
-                    "Architecture" => {
-                        trace!("Found field (architecture): {value:?}");
-                        draft.architecture = Some(value.to_string());
-                    }
-                    "Filename" => {
-                        let (folder, file_name) = value.rsplit_once('/').unwrap_or(("", value));
-                        trace!(
-                            "Found value of filename field ({value:?}), resolved to filename {file_name:?}"
-                        );
-                        draft.file_name = Some(file_name.to_string());
-                        let (_, folder) = folder.split_once('/').unwrap_or(("", value));
-                        let (_, folder) = folder.split_once('/').unwrap_or(("", value));
-                        draft.deb_folder = Some(folder.to_string());
-                    }
-                    _ => (),
+// Parse a `name (= version)` reference
+fn parse_built_using(value: &str) -> DebianSource {
+    let (name, version) = value
+        .split_once('(')
+        .map(|(name, version)| {
+            let version = version
+                .trim_end_matches(')')
+                .trim_start_matches('=')
+                .trim()
+                .to_string();
+            (name.trim(), Some(version))
+        })
+        .unwrap_or((value, None));
+
+    DebianSource {
+        name: name.to_string(),
+        version,
+    }
+}
+
+/// Iterator over the binary packages of a Packages index, reading one paragraph at a time
+pub struct PackagesReader<R> {
+    lines: std::io::Lines<R>,
+    draft: DraftBinaryPkg,
+    // the current field, continuation lines are appended until the next field starts
+    field: Option<(String, String)>,
+}
+
+impl<R: BufRead> PackagesReader<R> {
+    /// Read the paragraphs of an uncompressed Packages index
+    pub fn new(reader: R) -> Self {
+        PackagesReader {
+            lines: reader.lines(),
+            draft: DraftBinaryPkg::default(),
+            field: None,
+        }
+    }
+
+    fn finish_field(&mut self) -> Result<()> {
+        if let Some((key, value)) = self.field.take() {
+            self.draft.add_field(&key, &value)?;
+        }
+        Ok(())
+    }
+
+    fn finish_draft(&mut self) -> Option<Result<DebianBinaryPkg>> {
+        if let Err(err) = self.finish_field() {
+            self.draft = DraftBinaryPkg::default();
+            return Some(Err(err));
+        }
+
+        let draft = std::mem::take(&mut self.draft);
+        if draft == DraftBinaryPkg::default() {
+            return None;
+        }
+        let bin = draft.try_into();
+        trace!("Learned debian binary pkg: {:?}", bin);
+        Some(bin)
+    }
+}
+
+impl<R: BufRead> Iterator for PackagesReader<R> {
+    type Item = Result<DebianBinaryPkg>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next() {
+                Some(Ok(line)) => line,
+                Some(Err(err)) => return Some(Err(err.into())),
+                None => return self.finish_draft(),
+            };
+
+            if line.is_empty() {
+                if let Some(bin) = self.finish_draft() {
+                    return Some(bin);
+                }
+            } else if line.starts_with(' ') || line.starts_with('\t') {
+                if let Some((_, value)) = &mut self.field {
+                    value.push('\n');
+                    value.push_str(&line);
                 }
+            } else if let Some((key, value)) = line.split_once(':') {
+                if let Err(err) = self.finish_field() {
+                    return Some(Err(err));
+                }
+                self.field = Some((key.to_string(), value.trim_start().to_string()));
             }
         }
     }
+}
+
+/// A downloaded Packages index, stored in the blob store of the database
+pub struct PackagesIndex {
+    /// The url or path the index was read from
+    pub url: String,
+    /// The sha256 of the index, snapshots refer to the same file
+    pub sha256: String,
+    /// Needs to be stored once the index has been read successfully
+    pub cache: Option<PendingCache>,
+}
 
-    Ok(out)
+/// Read all binary packages of an uncompressed Packages index into memory.
+///
+/// Use [`PackagesReader`] to read them one at a time instead.
+pub fn parse_packages_db<R: Read>(reader: R) -> Result<Vec<DebianBinaryPkg>> {
+    PackagesReader::new(BufReader::new(reader)).collect()
+}
+
+/// Read all binary packages of a Packages index into memory, the compression is detected from
+/// the data.
+///
+/// Use [`read_compressed_packages_db`] to read them one at a time instead.
+pub fn parse_compressed_packages_db(packages_db: &[u8]) -> Result<Vec<DebianBinaryPkg>> {
+    read_compressed_packages_db(packages_db, "")?.collect()
+}
+
+/// Detect the compression of a Packages index and decode it while reading
+pub fn read_compressed_packages_db<'a, R: Read + Send + 'a>(
+    reader: R,
+    name: &str,
+) -> Result<PackagesReader<BufReader<Box<dyn Read + Send + 'a>>>> {
+    let mut reader = BufReader::new(reader);
+    let compression = Compression::detect(reader.fill_buf()?, name);
+    let reader = compression.decoder(reader)?;
+    Ok(PackagesReader::new(BufReader::new(reader)))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn read_all(data: &[u8], name: &str) -> Result<Vec<DebianBinaryPkg>> {
+        read_compressed_packages_db(data, name)?.collect()
+    }
+
+    #[test]
+    fn test_artifact_url_contrib() {
+        let pkgs = parse_packages_db(STEAMCMD).unwrap();
+        assert_eq!(pkgs.len(), 1);
+        assert_eq!(pkgs[0].deb_folder, "s/steamcmd");
+        assert_eq!(
+            pkgs[0].url("http://ftp.de.debian.org/debian/"),
+            "http://ftp.de.debian.org/debian/pool/contrib/s/steamcmd/steamcmd_0~20180105-4_i386.deb"
+        );
+    }
+
+    const STEAMCMD: &[u8] = b"Package: steamcmd
+Source: steamcmd
+Version: 0~20180105-4
+Architecture: i386
+Filename: pool/contrib/s/steamcmd/steamcmd_0~20180105-4_i386.deb
+
+";
+
+    #[test]
+    fn test_binnmu_versions() {
+        let pkgs = parse_packages_db(
+            b"Package: courier-base
+Source: courier (1.0.16-3)
+Version: 1.0.16-3+b1
+Architecture: amd64
+Filename: pool/main/c/courier/courier-base_1.0.16-3+b1_amd64.deb
+
+Package: courier-doc
+Source: courier
+Version: 1.0.16-3
+Architecture: all
+Filename: pool/main/c/courier/courier-doc_1.0.16-3_all.deb
+
+"
+            .as_slice(),
+        )
+        .unwrap();
+        assert_eq!(pkgs[0].source_version(), "1.0.16-3");
+        assert_eq!(pkgs[0].buildinfo_version(), "1.0.16-3+b1");
+        assert_eq!(pkgs[1].source_version(), "1.0.16-3");
+        assert_eq!(pkgs[1].buildinfo_version(), "1.0.16-3");
+    }
+
+    fn assert_steamcmd(pkgs: Vec<DebianBinaryPkg>) {
+        assert_eq!(pkgs.len(), 1);
+        assert_eq!(pkgs[0].name, "steamcmd");
+        assert_eq!(pkgs[0].version, "0~20180105-4");
+    }
+
+    #[test]
+    fn parse_uncompressed_package_db() {
+        let pkgs = read_all(STEAMCMD, "./Packages").unwrap();
+        assert_steamcmd(pkgs);
+    }
+
+    #[test]
+    fn parse_xz_package_db() {
+        let mut data = Vec::new();
+        xz2::read::XzEncoder::new(STEAMCMD, 6)
+            .read_to_end(&mut data)
+            .unwrap();
+        let pkgs = read_all(&data, "./Packages.xz").unwrap();
+        assert_steamcmd(pkgs);
+        let pkgs = parse_compressed_packages_db(&data).unwrap();
+        assert_steamcmd(pkgs);
+    }
+
+    #[test]
+    fn parse_gzip_package_db() {
+        let mut data = Vec::new();
+        flate2::read::GzEncoder::new(STEAMCMD, flate2::Compression::default())
+            .read_to_end(&mut data)
+            .unwrap();
+        // the magic bytes take precedence over the file extension
+        let pkgs = read_all(&data, "./Packages").unwrap();
+        assert_steamcmd(pkgs);
+    }
+
+    #[test]
+    fn parse_bzip2_package_db() {
+        let mut data = Vec::new();
+        bzip2::read::BzEncoder::new(STEAMCMD, bzip2::Compression::default())
+            .read_to_end(&mut data)
+            .unwrap();
+        let pkgs = read_all(&data, "./Packages.bz2").unwrap();
+        assert_steamcmd(pkgs);
+    }
+
+    #[test]
+    fn parse_zstd_package_db() {
+        let data = zstd::encode_all(STEAMCMD, 0).unwrap();
+        let pkgs = read_all(&data, "./Packages.zst").unwrap();
+        assert_steamcmd(pkgs);
+    }
+
+    #[test]
+    fn read_package_db_incrementally() {
+        let data = b"Package: steamcmd
+Version: 0~20180105-4
+Architecture: i386
+Filename: pool/contrib/s/steamcmd/steamcmd_0~20180105-4_i386.deb
+
+
+Package: sniffglue
+Source: rust-sniffglue
+Version: 0.14.0-2
+Architecture: amd64
+Filename: pool/main/r/rust-sniffglue/sniffglue_0.14.0-2_amd64.deb";
+        let mut pkgs = read_compressed_packages_db(&data[..], "Packages").unwrap();
+        let pkg = pkgs.next().unwrap().unwrap();
+        assert_eq!(pkg.name, "steamcmd");
+        assert_eq!(pkg.source.name, "steamcmd");
+        let pkg = pkgs.next().unwrap().unwrap();
+        assert_eq!(pkg.name, "sniffglue");
+        assert_eq!(pkg.source.name, "rust-sniffglue");
+        assert!(pkgs.next().is_none());
+    }
+
+    #[test]
+    fn parse_multiline_description() {
+        let data = b"Package: hello
+Version: 2.10-2
+Architecture: amd64
+Description: example package based on GNU hello
+ The GNU hello program produces a familiar, friendly greeting.
+ .
+ Seriously though: this is an example.
+Filename: pool/main/h/hello/hello_2.10-2_amd64.deb
+Size: 56132
+
+";
+        let pkgs = parse_packages_db(&data[..]).unwrap();
+        assert_eq!(pkgs.len(), 1);
+        assert_eq!(
+            pkgs[0].description.as_deref(),
+            Some(
+                "example package based on GNU hello
+ The GNU hello program produces a familiar, friendly greeting.
+ .
+ Seriously though: this is an example."
+            )
+        );
+        assert_eq!(pkgs[0].size, Some(56132));
+        assert_eq!(pkgs[0].file_name, "hello_2.10-2_amd64.deb");
+        assert!(pkgs[0].built_using.is_empty());
+    }
+
     #[test]
     fn parse_package_db_sniffglue() {
         let data = br#"Package: sniffglue
@@ -157,7 +525,7 @@ MD5sum: 177f9229266ad5eef3fb42fff0c07345
 SHA256: 448c781a9e594227bc9f0d6c65b8beba2b3add68d3583020de188d4cfa365b40
 
 "#;
-        let pkgs = parse_packages_db(&mut &data[..]).unwrap();
+        let pkgs = parse_packages_db(&data[..]).unwrap();
         assert_eq!(
             pkgs,
             &[DebianBinaryPkg {
@@ -170,6 +538,35 @@ SHA256: 448c781a9e594227bc9f0d6c65b8beba2b3add68d3583020de188d4cfa365b40
                 architecture: "amd64".to_string(),
                 file_name: "sniffglue_0.14.0-2_amd64.deb".to_string(),
                 deb_folder: "r/rust-sniffglue".to_string(),
+                path: "pool/main/r/rust-sniffglue/sniffglue_0.14.0-2_amd64.deb".to_string(),
+                size: Some(732980),
+                installed_size: Some(2344),
+                sha256: Some(
+                    "448c781a9e594227bc9f0d6c65b8beba2b3add68d3583020de188d4cfa365b40".to_string()
+                ),
+                md5sum: Some("177f9229266ad5eef3fb42fff0c07345".to_string()),
+                built_using: vec![
+                    DebianSource {
+                        name: "rust-nix".to_string(),
+                        version: Some("0.23.0-1".to_string()),
+                    },
+                    DebianSource {
+                        name: "rust-pktparse".to_string(),
+                        version: Some("0.5.0-1".to_string()),
+                    },
+                    DebianSource {
+                        name: "rust-seccomp-sys".to_string(),
+                        version: Some("0.1.3-1".to_string()),
+                    },
+                    DebianSource {
+                        name: "rustc".to_string(),
+                        version: Some("1.56.0+dfsg1-2".to_string()),
+                    },
+                ],
+                multi_arch: Some("allowed".to_string()),
+                section: Some("net".to_string()),
+                priority: Some("optional".to_string()),
+                description: Some("Secure multithreaded packet sniffer".to_string()),
             }]
         );
     }
@@ -0,0 +1,304 @@
+use crate::version;
+use rebuilderd_common::PkgGroup;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Build groups without buildinfo file point to `<buildinfo-base>/missing-buildinfo/<src>/<version>`
+pub fn is_missing_buildinfo(input_url: Option<&str>) -> bool {
+    input_url.is_none_or(|url| url.contains("/missing-buildinfo/"))
+}
+
+/// A build group that was added or removed
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Group {
+    /// The name of the source package
+    pub name: String,
+    /// The version of the source package
+    pub version: String,
+    /// The architecture of the build group
+    pub architecture: String,
+    /// The buildinfo file of the build group
+    pub input_url: Option<String>,
+}
+
+impl From<&PkgGroup> for Group {
+    fn from(group: &PkgGroup) -> Group {
+        Group {
+            name: group.name.clone(),
+            version: group.version.clone(),
+            architecture: group.architecture.clone(),
+            input_url: group.input_url.clone(),
+        }
+    }
+}
+
+/// A build group that got a different version
+#[derive(Debug, PartialEq, Serialize)]
+pub struct VersionChange {
+    /// The name of the source package
+    pub name: String,
+    /// The architecture of the build group
+    pub architecture: String,
+    /// The version in the older build groups
+    pub old_version: String,
+    /// The version in the newer build groups
+    pub new_version: String,
+}
+
+/// A build group with the same version that got a different `input_url`
+#[derive(Debug, PartialEq, Serialize)]
+pub struct InputUrlChange {
+    /// The name of the source package
+    pub name: String,
+    /// The version of the source package
+    pub version: String,
+    /// The architecture of the build group
+    pub architecture: String,
+    /// The `input_url` in the older build groups
+    pub old_input_url: Option<String>,
+    /// The `input_url` in the newer build groups
+    pub new_input_url: Option<String>,
+}
+
+/// Changes between two lists of build groups, e.g. the output of two nightly imports
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Diff {
+    /// Build groups that only exist in the newer build groups
+    pub added: Vec<Group>,
+    /// Build groups that only exist in the older build groups
+    pub removed: Vec<Group>,
+    /// Build groups with the same name and architecture but a different version
+    pub version_changes: Vec<VersionChange>,
+    /// The `input_url` switched from `missing-buildinfo` to a real buildinfo file
+    pub buildinfo_found: Vec<InputUrlChange>,
+    /// The `input_url` switched from a real buildinfo file to `missing-buildinfo`
+    pub buildinfo_lost: Vec<InputUrlChange>,
+    /// The `input_url` switched to a different buildinfo file
+    pub input_url_changes: Vec<InputUrlChange>,
+}
+
+impl Diff {
+    /// Compare two lists of build groups.
+    ///
+    /// Groups with the same name, architecture and version are compared by their `input_url`.
+    /// The remaining groups with the same name and architecture are paired up as version
+    /// changes, everything else was added or removed.
+    pub fn new(old: &[PkgGroup], new: &[PkgGroup]) -> Diff {
+        let mut diff = Diff::default();
+
+        let mut old_by_version = HashMap::new();
+        for group in old {
+            old_by_version.insert((&group.name, &group.architecture, &group.version), group);
+        }
+
+        // groups without an exact match, by name and architecture
+        let mut unmatched = BTreeMap::<_, (Vec<&PkgGroup>, Vec<&PkgGroup>)>::new();
+        for group in new {
+            let key = (&group.name, &group.architecture, &group.version);
+            match old_by_version.remove(&key) {
+                Some(old) => diff.compare_input_url(old, group),
+                None => unmatched
+                    .entry((&group.name, &group.architecture))
+                    .or_default()
+                    .1
+                    .push(group),
+            }
+        }
+        for group in old_by_version.into_values() {
+            unmatched
+                .entry((&group.name, &group.architecture))
+                .or_default()
+                .0
+                .push(group);
+        }
+
+        for (_, (mut old, mut new)) in unmatched {
+            old.sort_by(|a, b| version::compare(&a.version, &b.version));
+            new.sort_by(|a, b| version::compare(&a.version, &b.version));
+
+            let paired = old.len().min(new.len());
+            for (old, new) in old.iter().zip(&new) {
+                diff.version_changes.push(VersionChange {
+                    name: new.name.clone(),
+                    architecture: new.architecture.clone(),
+                    old_version: old.version.clone(),
+                    new_version: new.version.clone(),
+                });
+            }
+            diff.removed
+                .extend(old[paired..].iter().map(|g| Group::from(*g)));
+            diff.added
+                .extend(new[paired..].iter().map(|g| Group::from(*g)));
+        }
+
+        diff.sort();
+        diff
+    }
+
+    fn compare_input_url(&mut self, old: &PkgGroup, new: &PkgGroup) {
+        if old.input_url == new.input_url {
+            return;
+        }
+
+        let change = InputUrlChange {
+            name: new.name.clone(),
+            version: new.version.clone(),
+            architecture: new.architecture.clone(),
+            old_input_url: old.input_url.clone(),
+            new_input_url: new.input_url.clone(),
+        };
+        match (
+            is_missing_buildinfo(old.input_url.as_deref()),
+            is_missing_buildinfo(new.input_url.as_deref()),
+        ) {
+            (true, false) => self.buildinfo_found.push(change),
+            (false, true) => self.buildinfo_lost.push(change),
+            _ => self.input_url_changes.push(change),
+        }
+    }
+
+    fn sort(&mut self) {
+        fn key(name: &str, architecture: &str) -> (String, String) {
+            (name.to_string(), architecture.to_string())
+        }
+        self.added
+            .sort_by_cached_key(|g| (key(&g.name, &g.architecture), g.version.clone()));
+        self.removed
+            .sort_by_cached_key(|g| (key(&g.name, &g.architecture), g.version.clone()));
+        self.version_changes
+            .sort_by_cached_key(|c| key(&c.name, &c.architecture));
+        for list in [
+            &mut self.buildinfo_found,
+            &mut self.buildinfo_lost,
+            &mut self.input_url_changes,
+        ] {
+            list.sort_by_cached_key(|c| key(&c.name, &c.architecture));
+        }
+    }
+
+    /// Nothing changed
+    pub fn is_empty(&self) -> bool {
+        *self == Diff::default()
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for group in &self.added {
+            writeln!(
+                f,
+                "+ {} {} {}",
+                group.name, group.version, group.architecture
+            )?;
+        }
+        for group in &self.removed {
+            writeln!(
+                f,
+                "- {} {} {}",
+                group.name, group.version, group.architecture
+            )?;
+        }
+        for change in &self.version_changes {
+            writeln!(
+                f,
+                "~ {} {} -> {} {}",
+                change.name, change.old_version, change.new_version, change.architecture
+            )?;
+        }
+        let urls = [
+            ("buildinfo found", &self.buildinfo_found),
+            ("buildinfo lost", &self.buildinfo_lost),
+            ("buildinfo changed", &self.input_url_changes),
+        ];
+        for (label, changes) in urls {
+            for change in changes {
+                writeln!(
+                    f,
+                    "* {} {} {}: {}: {} -> {}",
+                    change.name,
+                    change.version,
+                    change.architecture,
+                    label,
+                    change.old_input_url.as_deref().unwrap_or("none"),
+                    change.new_input_url.as_deref().unwrap_or("none")
+                )?;
+            }
+        }
+        write!(
+            f,
+            "{} added, {} removed, {} version changes, {} buildinfo found, {} buildinfo lost, {} buildinfo changed",
+            self.added.len(),
+            self.removed.len(),
+            self.version_changes.len(),
+            self.buildinfo_found.len(),
+            self.buildinfo_lost.len(),
+            self.input_url_changes.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(name: &str, version: &str, architecture: &str, input_url: &str) -> PkgGroup {
+        PkgGroup {
+            name: name.to_string(),
+            version: version.to_string(),
+            distro: "debian".to_string(),
+            suite: "main".to_string(),
+            architecture: architecture.to_string(),
+            input_url: Some(input_url.to_string()),
+            artifacts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn diff_groups() {
+        let old = vec![
+            group("rust-sniffglue", "0.14.0-2", "amd64", "https://buildinfos.debian.net/missing-buildinfo/rust-sniffglue/0.14.0-2"),
+            group("rust-sniffglue", "0.14.0-2", "all", "https://buildinfos.debian.net/buildinfo-pool/r/rust-sniffglue/rust-sniffglue_0.14.0-2_all.buildinfo"),
+            group("courier", "1.0.16-3", "amd64", "https://buildinfos.debian.net/buildinfo-pool/c/courier/courier_1.0.16-3_amd64.buildinfo"),
+            group("steamcmd", "0~20180105-4", "i386", "https://buildinfos.debian.net/buildinfo-pool/s/steamcmd/steamcmd_0~20180105-4_i386.buildinfo"),
+        ];
+        let new = vec![
+            group("rust-sniffglue", "0.14.0-2", "amd64", "https://buildinfos.debian.net/buildinfo-pool/r/rust-sniffglue/rust-sniffglue_0.14.0-2_amd64.buildinfo"),
+            group("rust-sniffglue", "0.14.0-2", "all", "https://buildinfos.debian.net/buildinfo-pool/r/rust-sniffglue/rust-sniffglue_0.14.0-2_all.buildinfo"),
+            group("courier", "1.0.16-3+b1", "amd64", "https://buildinfos.debian.net/buildinfo-pool/c/courier/courier_1.0.16-3+b1_amd64.buildinfo"),
+            group("hello", "2.10-2", "amd64", "https://buildinfos.debian.net/missing-buildinfo/hello/2.10-2"),
+        ];
+
+        let diff = Diff::new(&old, &new);
+        assert_eq!(diff.added, &[Group::from(&new[3])]);
+        assert_eq!(diff.removed, &[Group::from(&old[3])]);
+        assert_eq!(
+            diff.version_changes,
+            &[VersionChange {
+                name: "courier".to_string(),
+                architecture: "amd64".to_string(),
+                old_version: "1.0.16-3".to_string(),
+                new_version: "1.0.16-3+b1".to_string(),
+            }]
+        );
+        assert_eq!(
+            diff.buildinfo_found,
+            &[InputUrlChange {
+                name: "rust-sniffglue".to_string(),
+                version: "0.14.0-2".to_string(),
+                architecture: "amd64".to_string(),
+                old_input_url: old[0].input_url.clone(),
+                new_input_url: new[0].input_url.clone(),
+            }]
+        );
+        assert!(diff.buildinfo_lost.is_empty());
+        assert!(diff.input_url_changes.is_empty());
+
+        assert_eq!(
+            diff.to_string().lines().last(),
+            Some("1 added, 1 removed, 1 version changes, 1 buildinfo found, 0 buildinfo lost, 0 buildinfo changed")
+        );
+
+        assert!(Diff::new(&new, &new).is_empty());
+    }
+}
@@ -1,2 +1,3 @@
 pub use anyhow::{anyhow, bail, Context as _, Error, Result};
+#[allow(unused_imports)]
 pub use log::{debug, error, info, trace, warn};
@@ -1,26 +1,337 @@
+use crate::db::Database;
 use crate::errors::*;
-use crate::utils;
+use crate::models::http_cache::NewHttpCacheEntry;
+use crate::utils::{self, HttpClient};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use kuchiki::traits::*;
-use reqwest::Client;
+use kuchiki::NodeRef;
+use reqwest::header::CONTENT_TYPE;
+use serde::Deserialize;
+use std::fs;
 
-pub async fn fetch_buildinfo_hrefs(client: &Client, url: &str) -> Result<Vec<String>> {
-    let html = utils::fetch_http(client, url).await?;
-    parse_buildinfo_hrefs(&html)
+/// A buildinfo file in a directory listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListingEntry {
+    /// The link of the file, relative to the listing
+    pub href: String,
+    /// The modification time shown in the listing
+    pub mtime: Option<NaiveDateTime>,
+    /// The size in bytes, only approximate if the listing uses units like `10K`
+    pub size: Option<u64>,
 }
 
-pub fn parse_buildinfo_hrefs(html: &[u8]) -> Result<Vec<String>> {
-    let html = String::from_utf8_lossy(html);
-    let document = kuchiki::parse_html().one(html.as_ref());
+impl ListingEntry {
+    /// An entry without modification time and size
+    pub fn new(href: String) -> ListingEntry {
+        ListingEntry {
+            href,
+            mtime: None,
+            size: None,
+        }
+    }
+
+    // buildinfo files are named `<source>_<version>_<arch>.buildinfo`
+    fn name_parts(&self) -> Option<Vec<&str>> {
+        let name = self.href.rsplit('/').next()?;
+        let name = name.strip_suffix(".buildinfo")?;
+        let parts = name.split('_').collect::<Vec<_>>();
+        if parts.len() == 3 {
+            Some(parts)
+        } else {
+            None
+        }
+    }
+
+    /// The version from the file name, without epoch
+    pub fn version(&self) -> Option<&str> {
+        self.name_parts().map(|parts| parts[1])
+    }
+
+    /// The architecture from the file name, e.g. `amd64` or `all_source`
+    pub fn architecture(&self) -> Option<&str> {
+        self.name_parts().map(|parts| parts[2])
+    }
+
+    // the cache uses one line per entry: href, mtime and size separated by tabs
+    fn to_cache_line(&self) -> String {
+        let mtime = self
+            .mtime
+            .map(|mtime| mtime.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        let size = self.size.map(|size| size.to_string()).unwrap_or_default();
+        format!("{}\t{}\t{}", self.href, mtime, size)
+    }
+
+    fn from_cache_line(line: &str) -> ListingEntry {
+        let mut fields = line.split('\t');
+        let href = fields.next().unwrap_or_default().to_string();
+        let mtime = fields.next().and_then(parse_mtime);
+        let size = fields.next().and_then(|size| size.parse().ok());
+        ListingEntry { href, mtime, size }
+    }
+}
+
+/// Fetch the list of buildinfo files, unchanged directory listings are served from the cache
+pub async fn fetch_buildinfo_listing(
+    client: &HttpClient,
+    db: &Database,
+    url: &str,
+) -> Result<Vec<ListingEntry>> {
+    if utils::is_local_path(url) {
+        return list_buildinfo_dir(url);
+    }
+
+    let cached = db.http_cache(url)?;
+    let (etag, last_modified) = cached
+        .as_ref()
+        .map(|c| (c.etag.as_deref(), c.last_modified.as_deref()))
+        .unwrap_or_default();
+
+    info!("Downloading directory listing from {:?}", url);
+    let download = client
+        .get_conditional(url, etag, last_modified, |response| async move {
+            let (etag, last_modified) = utils::cache_validators(&response);
+            let content_type = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from);
+            let body = response
+                .bytes()
+                .await
+                .context("Failed to download content")?;
+            Ok((content_type, body, etag, last_modified))
+        })
+        .await?;
+
+    match download {
+        Some((content_type, body, etag, last_modified)) => {
+            let entries = parse_buildinfo_listing(content_type.as_deref(), &body)?;
+
+            if etag.is_some() || last_modified.is_some() {
+                // the parsed list is cached so nothing needs to be parsed on 304
+                let body = entries
+                    .iter()
+                    .map(ListingEntry::to_cache_line)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                db.set_http_cache(NewHttpCacheEntry {
+                    url: url.to_string(),
+                    etag,
+                    last_modified,
+                    body: Some(body.into_bytes()),
+                    blob: None,
+                })?;
+            }
+
+            Ok(entries)
+        }
+        None => {
+            let cached = cached.context("Server responded with 304 but nothing is cached")?;
+            debug!("Directory listing has not changed, using cached list");
+            let entries = String::from_utf8_lossy(cached.body.as_deref().unwrap_or_default())
+                .lines()
+                .map(ListingEntry::from_cache_line)
+                .collect();
+            Ok(entries)
+        }
+    }
+}
+
+/// List the buildinfo files in a directory of a local mirror
+pub fn list_buildinfo_dir(path: &str) -> Result<Vec<ListingEntry>> {
+    info!("Listing directory {:?}", path);
+    let dir = fs::read_dir(path)
+        .with_context(|| anyhow!("Failed to list directory from disk: {:?}", path))?;
+
+    let mut out = Vec::new();
+    for entry in dir {
+        let entry = entry?;
+        if let Some(name) = entry.file_name().to_str() {
+            if name.ends_with(".buildinfo") {
+                let metadata = entry.metadata()?;
+                let mtime = metadata
+                    .modified()
+                    .ok()
+                    .map(|mtime| DateTime::<Utc>::from(mtime).naive_utc());
+                out.push(ListingEntry {
+                    href: name.to_string(),
+                    mtime,
+                    size: Some(metadata.len()),
+                });
+            }
+        }
+    }
+    // sort the same way the http directory listing does
+    out.sort_by(|a, b| a.href.cmp(&b.href));
+
+    Ok(out)
+}
+
+// apache uses 1024 based units like `10K` or `5.8K`
+fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (num, unit) = match s.char_indices().last()? {
+        (i, 'K') => (&s[..i], 1024),
+        (i, 'M') => (&s[..i], 1024 * 1024),
+        (i, 'G') => (&s[..i], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+    let num = num.trim().parse::<f64>().ok()?;
+    Some((num * unit as f64).round() as u64)
+}
+
+// the formats used by apache, our cache, lighttpd and nginx
+fn parse_mtime(s: &str) -> Option<NaiveDateTime> {
+    let s = s.trim();
+    [
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%b-%d %H:%M:%S",
+        "%d-%b-%Y %H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+}
+
+/// The kind of directory listing, each webserver has its own format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListingFormat {
+    /// Apache `mod_autoindex`, also used for everything that isn't recognized
+    Apache,
+    /// The `mod_dirlisting` table
+    Lighttpd,
+    /// `autoindex` with the default html format
+    NginxHtml,
+    /// `autoindex` with `autoindex_format json`
+    NginxJson,
+}
+
+impl ListingFormat {
+    /// Detect the format from the content type of the response, or the shape of the body
+    pub fn detect(content_type: Option<&str>, body: &str) -> ListingFormat {
+        let is_json = content_type.map(|c| c.contains("json")).unwrap_or(false)
+            || body.trim_start().starts_with('[');
+        if is_json {
+            ListingFormat::NginxJson
+        } else if body.contains("<table summary=\"Directory Listing\"") {
+            ListingFormat::Lighttpd
+        } else if body.contains("<pre>") && !body.contains("<table") {
+            ListingFormat::NginxHtml
+        } else {
+            ListingFormat::Apache
+        }
+    }
+}
+
+/// Parse a directory listing, the format is detected automatically
+pub fn parse_buildinfo_listing(
+    content_type: Option<&str>,
+    body: &[u8],
+) -> Result<Vec<ListingEntry>> {
+    let body = String::from_utf8_lossy(body);
+    let format = ListingFormat::detect(content_type, &body);
+    debug!("Parsing directory listing as {:?}", format);
+    match format {
+        ListingFormat::Apache | ListingFormat::Lighttpd => Ok(parse_table_listing(&body)),
+        ListingFormat::NginxHtml => Ok(parse_pre_listing(&body)),
+        ListingFormat::NginxJson => parse_json_listing(&body),
+    }
+}
+
+/// The names of all buildinfo files in a directory listing.
+///
+/// This drops the modification time and size, use [`parse_buildinfo_listing`] to keep them.
+pub fn parse_buildinfo_hrefs(body: &[u8]) -> Result<Vec<String>> {
+    let entries = parse_buildinfo_listing(None, body)?;
+    Ok(entries.into_iter().map(|entry| entry.href).collect())
+}
+
+fn buildinfo_links(html: &str) -> impl Iterator<Item = (String, NodeRef)> {
+    let document = kuchiki::parse_html().one(html);
+    document.select("a").unwrap().filter_map(|css_match| {
+        let href = css_match.attributes.borrow().get("href")?.to_string();
+        if href.ends_with(".buildinfo") {
+            Some((href, css_match.as_node().clone()))
+        } else {
+            None
+        }
+    })
+}
+
+/// Parse an apache or lighttpd listing, the mtime and size are taken from the table columns after the link
+fn parse_table_listing(html: &str) -> Vec<ListingEntry> {
+    let mut out = Vec::new();
+    for (href, link) in buildinfo_links(html) {
+        let mut entry = ListingEntry::new(href);
+        if let Some(cell) = link.parent() {
+            let is_cell = cell
+                .as_element()
+                .map(|e| &*e.name.local == "td")
+                .unwrap_or(false);
+            if is_cell {
+                let mut columns = cell
+                    .following_siblings()
+                    .elements()
+                    .map(|td| td.text_contents());
+                entry.mtime = columns.next().and_then(|s| parse_mtime(&s));
+                entry.size = columns.next().and_then(|s| parse_size(&s));
+            }
+        }
+        out.push(entry);
+    }
+    out
+}
 
+/// Parse a nginx listing, the mtime and size are in the text after the link
+fn parse_pre_listing(html: &str) -> Vec<ListingEntry> {
     let mut out = Vec::new();
-    for css_match in document.select("a").unwrap() {
-        if let Some(href) = css_match.attributes.borrow().get("href") {
-            if href.ends_with(".buildinfo") {
-                out.push(href.to_string());
+    for (href, link) in buildinfo_links(html) {
+        let mut entry = ListingEntry::new(href);
+        if let Some(text) = link.next_sibling() {
+            if let Some(text) = text.as_text() {
+                let text = text.borrow();
+                let mut columns = text.split_whitespace();
+                if let (Some(date), Some(time)) = (columns.next(), columns.next()) {
+                    entry.mtime = parse_mtime(&format!("{} {}", date, time));
+                }
+                entry.size = columns.next().and_then(parse_size);
             }
         }
+        out.push(entry);
     }
+    out
+}
+
+#[derive(Debug, Deserialize)]
+struct NginxJsonEntry {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    mtime: Option<String>,
+    size: Option<u64>,
+}
 
+/// Parse a nginx listing with `autoindex_format json`
+fn parse_json_listing(body: &str) -> Result<Vec<ListingEntry>> {
+    let list = serde_json::from_str::<Vec<NginxJsonEntry>>(body)
+        .context("Failed to parse json directory listing")?;
+
+    let mut out = Vec::new();
+    for item in list {
+        if item.kind != "file" || !item.name.ends_with(".buildinfo") {
+            continue;
+        }
+        let mtime = item
+            .mtime
+            .and_then(|mtime| httpdate::parse_http_date(&mtime).ok())
+            .map(|mtime| DateTime::<Utc>::from(mtime).naive_utc());
+        out.push(ListingEntry {
+            href: item.name,
+            mtime,
+            size: item.size,
+        });
+    }
     Ok(out)
 }
 
@@ -194,7 +505,26 @@ mod tests {
 <address>Apache/2.4.52 (Debian) Server at buildinfos.debian.net Port 443</address>
 </body></html>
 "#;
+        let entries = parse_buildinfo_listing(None, html).unwrap();
+        assert_eq!(
+            entries[0],
+            ListingEntry {
+                href: "rust-sniffglue_0.8.2-1_arm64.buildinfo".to_string(),
+                mtime: Some(parse_mtime("2018-11-23 14:26").unwrap()),
+                size: Some(10240),
+            }
+        );
+        let last = entries.last().unwrap();
+        assert_eq!(last.mtime, parse_mtime("2021-12-06 21:06"));
+        assert_eq!(last.size, Some(7066));
+        assert_eq!(last.version(), Some("0.14.0-2"));
+        assert_eq!(last.architecture(), Some("source"));
+
         let hrefs = parse_buildinfo_hrefs(html).unwrap();
+        assert_eq!(
+            hrefs,
+            entries.into_iter().map(|e| e.href).collect::<Vec<_>>()
+        );
         assert_eq!(
             &hrefs,
             &[
@@ -348,4 +678,145 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size(" 10K"), Some(10240));
+        assert_eq!(parse_size("1.5M"), Some(1572864));
+        assert_eq!(parse_size("523"), Some(523));
+        assert_eq!(parse_size("  - "), None);
+    }
+
+    #[test]
+    fn test_cache_line_roundtrip() {
+        let entry = ListingEntry {
+            href: "rust-sniffglue_0.14.0-2_amd64.buildinfo".to_string(),
+            mtime: parse_mtime("2021-12-06 21:06"),
+            size: Some(12288),
+        };
+        assert_eq!(ListingEntry::from_cache_line(&entry.to_cache_line()), entry);
+        // lists cached by older versions only contain the href
+        assert_eq!(
+            ListingEntry::from_cache_line("rust-sniffglue_0.14.0-2_amd64.buildinfo"),
+            ListingEntry::new("rust-sniffglue_0.14.0-2_amd64.buildinfo".to_string())
+        );
+    }
+
+    const NGINX_JSON: &[u8] = br#"[
+{ "name":"rust-sniffglue_0.14.0-2_amd64.buildinfo", "type":"file", "mtime":"Mon, 06 Dec 2021 21:06:32 GMT", "size":12288 },
+{ "name":"rust-sniffglue_0.14.0-2_source.buildinfo", "type":"file", "mtime":"Mon, 06 Dec 2021 21:06:12 GMT", "size":7066 },
+{ "name":"old", "type":"directory", "mtime":"Sat, 29 Jan 2022 08:13:42 GMT" },
+{ "name":"README", "type":"file", "mtime":"Sat, 29 Jan 2022 08:13:42 GMT", "size":42 }
+]
+"#;
+
+    const NGINX_HTML: &[u8] = br#"<html>
+<head><title>Index of /buildinfo-pool/r/rust-sniffglue/</title></head>
+<body>
+<h1>Index of /buildinfo-pool/r/rust-sniffglue/</h1><hr><pre><a href="../">../</a>
+<a href="old/">old/</a>                                               29-Jan-2022 08:13                   -
+<a href="rust-sniffglue_0.14.0-2_amd64.buildinfo">rust-sniffglue_0.14.0-2_amd64.buildinfo</a>            06-Dec-2021 21:06               12288
+<a href="rust-sniffglue_0.14.0-2_source.buildinfo">rust-sniffglue_0.14.0-2_source.buildinfo</a>           06-Dec-2021 21:06                7066
+</pre><hr></body>
+</html>
+"#;
+
+    const LIGHTTPD: &[u8] = br#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE html>
+<html>
+<head>
+<title>Index of /buildinfo-pool/r/rust-sniffglue/</title>
+</head>
+<body>
+<h2>Index of /buildinfo-pool/r/rust-sniffglue/</h2>
+<div class="list">
+<table summary="Directory Listing" cellpadding="0" cellspacing="0">
+<thead><tr><th class="n">Name</th><th class="m">Last Modified</th><th class="s">Size</th><th class="t">Type</th></tr></thead>
+<tbody>
+<tr class="d"><td class="n"><a href="../">..</a>/</td><td class="m">&nbsp;</td><td class="s">- &nbsp;</td><td class="t">Directory</td></tr>
+<tr><td class="n"><a href="rust-sniffglue_0.14.0-2_amd64.buildinfo">rust-sniffglue_0.14.0-2_amd64.buildinfo</a></td><td class="m">2021-Dec-06 21:06:32</td><td class="s">12.0K</td><td class="t">application/octet-stream</td></tr>
+<tr><td class="n"><a href="rust-sniffglue_0.14.0-2_source.buildinfo">rust-sniffglue_0.14.0-2_source.buildinfo</a></td><td class="m">2021-Dec-06 21:06:12</td><td class="s">6.9K</td><td class="t">application/octet-stream</td></tr>
+</tbody>
+</table>
+</div>
+<div class="foot">lighttpd/1.4.59</div>
+</body>
+</html>
+"#;
+
+    fn datetime(s: &str) -> Option<NaiveDateTime> {
+        Some(NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap())
+    }
+
+    #[test]
+    fn test_detect_listing_format() {
+        assert_eq!(
+            ListingFormat::detect(Some("application/json"), ""),
+            ListingFormat::NginxJson
+        );
+        let detect = |body| ListingFormat::detect(None, &String::from_utf8_lossy(body));
+        assert_eq!(detect(NGINX_JSON), ListingFormat::NginxJson);
+        assert_eq!(detect(NGINX_HTML), ListingFormat::NginxHtml);
+        assert_eq!(detect(LIGHTTPD), ListingFormat::Lighttpd);
+    }
+
+    #[test]
+    fn test_nginx_json_listing() {
+        let entries = parse_buildinfo_listing(Some("application/json"), NGINX_JSON).unwrap();
+        assert_eq!(
+            entries,
+            &[
+                ListingEntry {
+                    href: "rust-sniffglue_0.14.0-2_amd64.buildinfo".to_string(),
+                    mtime: datetime("2021-12-06 21:06:32"),
+                    size: Some(12288),
+                },
+                ListingEntry {
+                    href: "rust-sniffglue_0.14.0-2_source.buildinfo".to_string(),
+                    mtime: datetime("2021-12-06 21:06:12"),
+                    size: Some(7066),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nginx_html_listing() {
+        let entries = parse_buildinfo_listing(Some("text/html"), NGINX_HTML).unwrap();
+        assert_eq!(
+            entries,
+            &[
+                ListingEntry {
+                    href: "rust-sniffglue_0.14.0-2_amd64.buildinfo".to_string(),
+                    mtime: datetime("2021-12-06 21:06:00"),
+                    size: Some(12288),
+                },
+                ListingEntry {
+                    href: "rust-sniffglue_0.14.0-2_source.buildinfo".to_string(),
+                    mtime: datetime("2021-12-06 21:06:00"),
+                    size: Some(7066),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lighttpd_listing() {
+        let entries = parse_buildinfo_listing(Some("text/html"), LIGHTTPD).unwrap();
+        assert_eq!(
+            entries,
+            &[
+                ListingEntry {
+                    href: "rust-sniffglue_0.14.0-2_amd64.buildinfo".to_string(),
+                    mtime: datetime("2021-12-06 21:06:32"),
+                    size: Some(12288),
+                },
+                ListingEntry {
+                    href: "rust-sniffglue_0.14.0-2_source.buildinfo".to_string(),
+                    mtime: datetime("2021-12-06 21:06:12"),
+                    size: Some(7066),
+                },
+            ]
+        );
+    }
 }
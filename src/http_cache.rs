@@ -0,0 +1,222 @@
+use crate::blobs::Blob;
+use crate::db::Database;
+use crate::errors::*;
+use crate::models::http_cache::NewHttpCacheEntry;
+use crate::utils::{self, HttpClient};
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+/// A downloaded file whose cache entry is written once it has been read successfully
+pub struct PendingCache {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    blob: String,
+}
+
+impl PendingCache {
+    pub fn store(self, db: &Database) -> Result<()> {
+        debug!(
+            "Storing blob {:?} in http cache for {:?}",
+            self.blob, self.url
+        );
+        db.set_http_cache(NewHttpCacheEntry {
+            url: self.url,
+            etag: self.etag,
+            last_modified: self.last_modified,
+            body: None,
+            blob: Some(self.blob),
+        })
+    }
+}
+
+/// Download a file into the blob store of the database, without keeping it in memory.
+///
+/// Unchanged files are served from the cache with a conditional request, local paths are copied
+/// into the blob store. If the content has changed, the cache entry needs to be stored with the
+/// returned `PendingCache` after the file has been read successfully.
+pub async fn fetch_blob(
+    client: &HttpClient,
+    db: &Database,
+    url: &str,
+) -> Result<(Blob, Option<PendingCache>)> {
+    if utils::is_local_path(url) {
+        info!("Reading from disk {:?}", url);
+        let mut file =
+            File::open(url).with_context(|| anyhow!("Failed to open file from disk: {:?}", url))?;
+        let mut writer = db.blobs().writer()?;
+        io::copy(&mut file, &mut writer)?;
+        return Ok((writer.commit()?, None));
+    }
+
+    // only use the cache if the file is still in the blob store
+    let mut cached = None;
+    if let Some(entry) = db.http_cache(url)? {
+        if let Some(blob) = entry.blob.as_deref() {
+            cached = db.blobs().stat(blob)?.map(|blob| (entry, blob));
+        }
+    }
+    let (etag, last_modified) = cached
+        .as_ref()
+        .map(|(c, _)| (c.etag.as_deref(), c.last_modified.as_deref()))
+        .unwrap_or_default();
+
+    info!("Downloading {:?}", url);
+    let download = client
+        .get_conditional(url, etag, last_modified, |mut response| async move {
+            // every attempt starts with a new writer, a partial download is discarded on drop
+            let mut writer = db.blobs().writer()?;
+            let (etag, last_modified) = utils::cache_validators(&response);
+            while let Some(chunk) = response
+                .chunk()
+                .await
+                .context("Failed to download content")?
+            {
+                writer.write_all(&chunk)?;
+            }
+            Ok((writer.commit()?, etag, last_modified))
+        })
+        .await?;
+
+    match download {
+        Some((blob, etag, last_modified)) => {
+            debug!("Downloaded {} bytes", blob.size);
+            let pending = if etag.is_some() || last_modified.is_some() {
+                Some(PendingCache {
+                    url: url.to_string(),
+                    etag,
+                    last_modified,
+                    blob: blob.sha256.clone(),
+                })
+            } else {
+                None
+            };
+            Ok((blob, pending))
+        }
+        None => {
+            let (_, blob) = cached.context("Server responded with 304 but nothing is cached")?;
+            info!("Using cached copy of {:?}", url);
+            Ok((blob, None))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::{ETAG, IF_NONE_MATCH};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server, StatusCode};
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // Serves a fixed body with an etag and answers conditional requests with 304
+    async fn mock_server() -> String {
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                let response = if req.headers().get(IF_NONE_MATCH).is_some() {
+                    Response::builder()
+                        .status(StatusCode::NOT_MODIFIED)
+                        .body(Body::empty())
+                } else {
+                    Response::builder()
+                        .header(ETAG, "\"1\"")
+                        .body(Body::from("Package: sniffglue\n"))
+                };
+                Ok::<_, Infallible>(response.unwrap())
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let url = format!("http://{}/Packages", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    #[tokio::test]
+    async fn fetch_blob_with_cache() {
+        let db = Database::open(":memory:").unwrap();
+        let client = HttpClient::new(reqwest::Client::new());
+        let url = mock_server().await;
+
+        let (blob, pending) = fetch_blob(&client, &db, &url).await.unwrap();
+        assert_eq!(blob.size, 19);
+        let mut body = String::new();
+        db.blobs()
+            .open(&blob.sha256)
+            .unwrap()
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "Package: sniffglue\n");
+
+        // nothing is cached until the pending entry is stored
+        assert!(db.http_cache(&url).unwrap().is_none());
+        pending.unwrap().store(&db).unwrap();
+
+        let (cached, pending) = fetch_blob(&client, &db, &url).await.unwrap();
+        assert_eq!(cached, blob);
+        assert!(pending.is_none());
+    }
+
+    // Aborts the connection in the middle of the body on the first request
+    async fn flaky_server(requests: Arc<AtomicUsize>) -> String {
+        let make_svc = make_service_fn(move |_| {
+            let requests = requests.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_req: Request<Body>| {
+                    let attempt = requests.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        let body = if attempt == 0 {
+                            let (mut sender, body) = Body::channel();
+                            tokio::spawn(async move {
+                                sender.send_data("Package: ".into()).await.ok();
+                                sender.abort();
+                            });
+                            body
+                        } else {
+                            Body::from("Package: sniffglue\n")
+                        };
+                        let response = Response::builder()
+                            .header("content-length", "19")
+                            .body(body);
+                        Ok::<_, Infallible>(response.unwrap())
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let url = format!("http://{}/Packages", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    #[tokio::test]
+    async fn fetch_blob_retries_broken_body() {
+        let db = Database::open(":memory:").unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let url = flaky_server(requests.clone()).await;
+
+        let client = HttpClient::new(reqwest::Client::new());
+        assert!(fetch_blob(&client, &db, &url).await.is_err());
+
+        let client = client.retries(1, Duration::from_millis(10));
+        requests.store(0, Ordering::SeqCst);
+        let (blob, _) = fetch_blob(&client, &db, &url).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        let mut body = String::new();
+        db.blobs()
+            .open(&blob.sha256)
+            .unwrap()
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "Package: sniffglue\n");
+
+        // the same applies to downloads that are kept in memory
+        requests.store(0, Ordering::SeqCst);
+        let content = utils::fetch_http(&client, &url).await.unwrap();
+        assert_eq!(content, b"Package: sniffglue\n");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+}
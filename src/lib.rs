@@ -0,0 +1,75 @@
+//! Find the buildinfo files of Debian binary packages and turn them into build groups for
+//! [rebuilderd](https://github.com/kpcyrd/rebuilderd).
+//!
+//! The binary is a thin wrapper around [`crawler::run`], the individual parts can be used on
+//! their own:
+//!
+//! - [`deb`] reads Packages indexes, optionally compressed
+//! - [`buildinfo`] parses and serializes `.buildinfo` files
+//! - [`html`] lists the buildinfo files of a source package on buildinfos.debian.net or a mirror
+//! - [`resolver`] finds the buildinfo file of a binary package, crawling the buildinfo pool if needed
+//! - [`db`] is the sqlite cache of buildinfo files, directory listings and http responses
+//! - [`snapshot`] stores the Packages indexes of a crawl so the output can be generated offline
+//! - [`HttpClient`] is used for all downloads, it retries failed downloads and limits the request rate
+//!
+//! ```no_run
+//! use rebuilderd_debian_buildinfo_crawler::deb;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let file = std::fs::File::open("Packages.xz")?;
+//! for pkg in deb::read_compressed_packages_db(file, "Packages.xz")? {
+//!     let pkg = pkg?;
+//!     println!("{} {} {}", pkg.name, pkg.version, pkg.buildinfo_version());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+#![warn(missing_docs)]
+
+#[macro_use]
+extern crate diesel;
+#[macro_use]
+extern crate diesel_migrations;
+
+mod blobs;
+/// Parsing and serializing `.buildinfo` files
+pub mod buildinfo;
+mod compression;
+/// Assemble the build groups for rebuilderd from Packages indexes
+pub mod crawler;
+/// The sqlite database that caches buildinfo files and http responses
+pub mod db;
+/// Parsing Debian Packages indexes
+pub mod deb;
+/// Changes between two sets of build groups
+pub mod diff;
+/// The error type and logging macros used by this crate
+pub mod errors;
+/// Directory listings of the buildinfo pool
+pub mod html;
+mod http_cache;
+mod migrations;
+// the diesel derives expand to impl blocks inside of constants
+#[allow(non_local_definitions)]
+pub(crate) mod models;
+/// Verifying OpenPGP signatures with `gpgv`
+pub mod pgp;
+/// Submitting build groups to a rebuilderd instance
+pub mod rebuilderd;
+/// Parsing Release files and finding their Packages indexes
+pub mod release;
+/// Machine-readable report of everything that went wrong during a crawl
+pub mod report;
+/// Matching binary packages with their buildinfo file
+pub mod resolver;
+// same for the `table!` macro
+#[allow(non_local_definitions)]
+mod schema;
+/// Copies of the Packages indexes of a crawl, to generate the build groups again without network access
+pub mod snapshot;
+mod utils;
+/// Parsing and comparing Debian package versions
+pub mod version;
+
+pub use crate::utils::HttpClient;
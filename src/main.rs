@@ -1,227 +1,343 @@
-#[macro_use]
-extern crate diesel;
-#[macro_use]
-extern crate diesel_migrations;
-
 mod args;
-mod buildinfo;
-mod db;
-mod deb;
-mod errors;
-mod html;
-mod migrations;
-mod models;
-mod schema;
-mod utils;
-
-use crate::db::Database;
-use crate::errors::*;
+
+use crate::args::{Args, SubCommand};
+use chrono::Utc;
 use clap::Parser;
 use env_logger::Env;
-use rebuilderd_common::{PkgArtifact, PkgGroup};
-use std::collections::HashMap;
+use rebuilderd_common::api::SuiteImport;
+use rebuilderd_common::PkgGroup;
+use rebuilderd_debian_buildinfo_crawler::buildinfo::Buildinfo;
+use rebuilderd_debian_buildinfo_crawler::crawler;
+use rebuilderd_debian_buildinfo_crawler::db::Database;
+use rebuilderd_debian_buildinfo_crawler::deb::PackagesIndex;
+use rebuilderd_debian_buildinfo_crawler::diff::Diff;
+use rebuilderd_debian_buildinfo_crawler::errors::*;
+use rebuilderd_debian_buildinfo_crawler::pgp::Keyring;
+use rebuilderd_debian_buildinfo_crawler::rebuilderd::Rebuilderd;
+use rebuilderd_debian_buildinfo_crawler::snapshot::{self, PendingSnapshot};
+use rebuilderd_debian_buildinfo_crawler::HttpClient;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
 use std::io::prelude::*;
+use std::path::Path;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = args::Args::parse();
+fn http_client(http: &args::Http) -> HttpClient {
+    HttpClient::new(reqwest::Client::new())
+        .retries(http.retries, http.retry_backoff)
+        .max_retry_delay(http.max_retry_delay)
+        .rate_limit(http.rate_limit)
+}
 
-    let logging = match args.verbose {
-        0 => "info",
-        1 => "info,rebuilderd_debian_buildinfo_crawler=debug",
-        2 => "debug",
-        _ => "debug,rebuilderd_debian_buildinfo_crawler=trace",
-    };
-    env_logger::init_from_env(Env::default().default_filter_or(logging));
+async fn open_indexes(
+    client: &HttpClient,
+    db: &Database,
+    keyring: Option<&Keyring>,
+    config: &crawler::Config,
+    index: &args::Index,
+) -> Result<Vec<PackagesIndex>> {
+    crawler::open_indexes(
+        client,
+        db,
+        keyring,
+        config,
+        &index.packages_dbs,
+        &index.releases,
+        &index.architectures,
+    )
+    .await
+}
 
-    let db = Database::open(&args.database)?;
-    let client = reqwest::Client::new();
-
-    let packages_db = utils::read_path_or_url(&client, &args.packages_db).await?;
-    let pkgs = deb::parse_compressed_packages_db(&packages_db)?;
-    info!("Found {} binary packages in index", pkgs.len());
-
-    let mut without_buildinfo = Vec::new();
-    let mut groups = HashMap::new();
-
-    for pkg in pkgs {
-        let buildinfo =
-            if let Some(buildinfo) = db.buildinfo_for_artifact_filename(&pkg.file_name)? {
-                debug!("Found buildinfo for {:?} in database", pkg.file_name);
-                debug!("Found buildinfo for {:?}: {:?}", pkg.file_name, buildinfo);
-                buildinfo
-            } else {
-                info!("Missing buildinfo for pkg: {:?}", pkg);
-                let url = format!(
-                    "https://buildinfos.debian.net/buildinfo-pool/{}/",
-                    pkg.deb_folder
-                );
-
-                let mut found = None;
-
-                if !args.skip_crawl {
-                    info!("Syncing buildinfos for source pkg: {:?}", url);
-                    match html::fetch_buildinfo_hrefs(&client, &url).await {
-                        Ok(mut buildinfos) => {
-                            buildinfos.reverse();
-
-                            for buildinfo_href in buildinfos {
-                                let url = format!("{}{}", url, buildinfo_href);
-
-                                if db.buildinfo_url_cache(&url)?.is_none() {
-                                    // Download buildinfo file
-                                    let buildinfo = utils::fetch_http(&client, &url).await?;
-                                    let buildinfo = String::from_utf8(buildinfo)?;
-
-                                    info!("Adding to cache for {:?}", url);
-                                    let artifacts = db.add_buildinfo(url, buildinfo)?;
-                                    if artifacts.contains(&pkg.file_name) {
-                                        debug!("Buildinfo contained artifact we're looking for");
-                                        let buildinfo = db
-                                        .buildinfo_for_artifact_filename(&pkg.file_name)?
-                                        .context(
-                                            "Database doesn't contain artifact we just inserted",
-                                        )?;
-                                        found = Some(buildinfo);
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                        Err(err) => warn!("Failed to fetch buildinfo directory listing: {:#}", err),
-                    }
-                }
-
-                if let Some(buildinfo) = found {
-                    info!("Finished syncing buildinfos");
-                    buildinfo
-                } else {
-                    warn!("Downloaded all buildinfos but none of them referenced our package");
-                    without_buildinfo.push(pkg);
-                    continue;
-                }
-            };
-
-        let group = groups
-            .entry(buildinfo.url.clone())
-            .or_insert_with(|| (buildinfo, HashMap::new()));
-        let list = group
-            .1
-            .entry(pkg.architecture.clone())
-            .or_insert_with(Vec::new);
-        list.push(pkg);
+async fn crawl(db: &Database, crawl: args::Crawl) -> Result<()> {
+    let client = http_client(&crawl.http);
+    let keyring = crawl
+        .index
+        .keyring
+        .as_deref()
+        .map(Keyring::open)
+        .transpose()?;
+    let config = crawl.crawler_config();
+
+    let indexes = open_indexes(&client, db, keyring.as_ref(), &config, &crawl.index).await?;
+    let snapshot = PendingSnapshot::record(&indexes);
+    let out = crawler::run(db, &client, keyring.as_ref(), &config, indexes).await?;
+    let id = snapshot.store(db, &out.groups)?;
+    info!(
+        "Stored Packages indexes and build groups as snapshot #{}",
+        id
+    );
+
+    if let Some(path) = &crawl.report {
+        info!("Writing report to {:?}...", path);
+        out.report.write(path)?;
     }
 
-    if !without_buildinfo.is_empty() {
-        warn!(
-            "Packages missing buildinfo files: {}",
-            without_buildinfo.len()
+    Ok(())
+}
+
+async fn export(db: &Database, export: args::Export) -> Result<()> {
+    let client = http_client(&export.http);
+    let keyring = export
+        .index
+        .keyring
+        .as_deref()
+        .map(Keyring::open)
+        .transpose()?;
+    let config = export.crawler_config();
+
+    let indexes = if export.fetch {
+        open_indexes(&client, db, keyring.as_ref(), &config, &export.index).await?
+    } else {
+        let index = &export.index;
+        if !index.packages_dbs.is_empty()
+            || !index.releases.is_empty()
+            || !index.architectures.is_empty()
+        {
+            bail!("Pass --fetch to download Packages indexes, export uses the latest snapshot by default");
+        }
+        let id = export.snapshot.unwrap_or(args::SnapshotId::Latest);
+        let (snapshot, indexes) = snapshot::open(db, id.id())?;
+        info!(
+            "Using snapshot #{} from {}",
+            snapshot.id, snapshot.created_at
         );
+        indexes
+    };
+    let out = crawler::run(db, &client, keyring.as_ref(), &config, indexes).await?;
+
+    if let Some(path) = &export.report {
+        info!("Writing report to {:?}...", path);
+        out.report.write(path)?;
     }
 
-    info!("Number of groups: {:?}", groups.len());
-    info!(
-        "Number of pkgs: {:?}",
-        groups.iter().fold(0, |acc, (_, x)| acc
-            + x.1.values().fold(0, |acc, x| acc + x.len()))
+    let mut stdout = io::stdout();
+    if let Some(url) = &export.rebuilderd_url {
+        let import = SuiteImport {
+            distro: config.distro.clone(),
+            suite: config.suite.clone(),
+            groups: out.groups,
+        };
+
+        if export.dry_run {
+            info!(
+                "Dry run, not submitting {} build groups to {:?}",
+                import.groups.len(),
+                url
+            );
+            serde_json::to_writer_pretty(&mut stdout, &import).ok();
+            writeln!(stdout).ok();
+        } else {
+            Rebuilderd::new(url, export.auth_cookie()?)?
+                .sync(&import)
+                .await?;
+            info!("Successfully submitted build groups to rebuilderd");
+        }
+    } else {
+        info!("Writing final json...");
+        serde_json::to_writer_pretty(&mut stdout, &out.groups).ok();
+        writeln!(stdout).ok();
+    }
+
+    Ok(())
+}
+
+fn without_epoch(version: &str) -> &str {
+    version.split_once(':').map_or(version, |(_, v)| v)
+}
+
+fn lookup(db: &Database, lookup: args::Lookup) -> Result<()> {
+    let path = Path::new(&lookup.file);
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| anyhow!("Invalid file name: {:?}", lookup.file))?;
+
+    let sha256 = if path.exists() {
+        let mut file = File::open(path)?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher)?;
+        Some(format!("{:x}", hasher.finalize()))
+    } else {
+        None
+    };
+    // <name>_<version>_<arch>.deb, the epoch is never part of the filename
+    let version = file_name.split('_').nth(1).unwrap_or_default();
+
+    println!("File:     {}", file_name);
+    println!(
+        "Sha256:   {}",
+        sha256.as_deref().unwrap_or("unknown, file not found")
     );
+    println!("Version:  {}", version);
 
-    info!("Generating build groups...");
-    let mut out = Vec::new();
-    for (_, (model, group)) in groups {
-        for (architecture, pkgs) in group {
-            let buildinfo = model.content.parse::<buildinfo::Buildinfo>()?;
-
-            let mut artifacts = Vec::new();
-            for pkg in pkgs {
-                let url = format!(
-                    "https://deb.debian.org/debian/pool/main/{}/{}",
-                    pkg.deb_folder, pkg.file_name
-                );
-                artifacts.push(PkgArtifact {
-                    name: pkg.name,
-                    version: pkg.version,
-                    url,
-                });
-            }
+    let matched = db.buildinfo_for_artifact(file_name, sha256.as_deref(), version)?;
+    let candidates = db.artifact_candidates(file_name)?;
 
-            out.push(PkgGroup {
-                name: buildinfo.source,
-                version: buildinfo.version,
+    println!();
+    for (artifact, buildinfo) in &candidates {
+        let mut reasons = Vec::new();
 
-                distro: args.distro.clone(),
-                suite: args.suite.clone(),
-                architecture,
+        match buildinfo.content.parse::<Buildinfo>() {
+            Ok(parsed) if without_epoch(&parsed.version) == version => {
+                reasons.push("version matches".to_string())
+            }
+            Ok(parsed) => reasons.push(format!("version {} differs", parsed.version)),
+            Err(err) => reasons.push(format!("failed to parse: {}", err)),
+        }
 
-                input_url: Some(model.url.clone()),
-                artifacts,
-            });
+        reasons.push(
+            match (&sha256, &artifact.sha256) {
+                (Some(a), Some(b)) if a == b => "checksum matches",
+                (Some(_), Some(_)) => "checksum differs",
+                (None, _) => "checksum not checked",
+                (_, None) => "no checksum in buildinfo",
+            }
+            .to_string(),
+        );
+
+        if let Some(signer) = &buildinfo.signer {
+            reasons.push(format!("signed by {}", signer));
         }
+
+        let selected = matched.as_ref().map(|m| m.id) == Some(buildinfo.id);
+        println!(
+            "{} {}\n    {}",
+            if selected { "*" } else { " " },
+            buildinfo.url,
+            reasons.join(", ")
+        );
     }
 
-    info!("Adding packages without buildinfo file...");
-    let mut missing_groups = HashMap::new();
-    for pkg in without_buildinfo {
-        let src = pkg.source.name.clone();
-        let version = pkg.source.version.as_deref().unwrap_or("0");
-
-        let key = format!("{src:?}-{version:?}");
-
-        let group = missing_groups
-            .entry(key)
-            .or_insert_with(|| (src, version.to_string(), HashMap::new()));
-        let list = group
-            .2
-            .entry(pkg.architecture.clone())
-            .or_insert_with(Vec::new);
-        list.push(pkg);
+    if candidates.is_empty() {
+        println!("No known buildinfo file lists this filename");
+    } else if matched.is_none() {
+        println!("None of the buildinfo files listing this filename has a matching checksum");
     }
 
-    for (_, (src, version, group)) in missing_groups {
-        for (architecture, pkgs) in group {
-            let mut artifacts = Vec::new();
-            for pkg in pkgs {
-                let url = format!(
-                    "https://deb.debian.org/debian/pool/main/{}/{}",
-                    pkg.deb_folder, pkg.file_name
-                );
-                artifacts.push(PkgArtifact {
-                    name: pkg.name,
-                    version: pkg.version,
-                    url,
-                });
-            }
+    Ok(())
+}
 
-            let missing_buildinfo_url = format!(
-                "https://buildinfos.debian.net/missing-buildinfo/{}/{}",
-                src, version
-            );
-            out.push(PkgGroup {
-                name: src.clone(),
-                version: version.clone(),
+fn format_bytes(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / 1024.0 / 1024.0)
+}
 
-                distro: args.distro.clone(),
-                suite: args.suite.clone(),
-                architecture,
+fn stats(db: &Database, database: &str) -> Result<()> {
+    let stats = db.stats()?;
 
-                input_url: Some(missing_buildinfo_url),
-                artifacts,
-            });
-        }
+    if let Ok(metadata) = std::fs::metadata(database) {
+        println!(
+            "Database size:               {}",
+            format_bytes(metadata.len())
+        );
+    }
+    println!(
+        "Buildinfo files:             {} ({} signed)",
+        stats.buildinfos, stats.signed_buildinfos
+    );
+    println!("Artifacts:                   {}", stats.artifacts);
+    println!(
+        "Http cache:                  {} entries",
+        stats.http_cache_entries
+    );
+    println!(
+        "Stored files:                {} ({})",
+        stats.blobs,
+        format_bytes(stats.blob_bytes)
+    );
+    println!("Packages without buildinfo:  {}", stats.missing_buildinfos);
+    println!("Parse failures:              {}", stats.parse_failures);
+    println!("Releases:                    {}", stats.releases);
+    println!("Snapshots:                   {}", stats.snapshots);
+
+    if stats.snapshots > 0 {
+        let (snapshot, indexes) = snapshot::open(db, None)?;
+        let coverage = snapshot::coverage(db, &indexes)?;
+        let percent = if coverage.packages > 0 {
+            coverage.with_buildinfo as f64 * 100.0 / coverage.packages as f64
+        } else {
+            0.0
+        };
+        println!(
+            "Coverage of snapshot #{}:     {} of {} packages have a buildinfo file ({:.1}%)",
+            snapshot.id, coverage.with_buildinfo, coverage.packages, percent
+        );
     }
 
-    info!("Sorting list...");
-    out.sort_by(|a, b| {
-        a.name
-            .cmp(&b.name)
-            .then(a.version.cmp(&b.version))
-            .then(a.architecture.cmp(&b.architecture))
-    });
+    Ok(())
+}
+
+fn gc(db: &Database, gc: args::Gc) -> Result<()> {
+    let before = Utc::now().naive_utc() - chrono::Duration::from_std(gc.older_than)?;
+    let stats = db.gc(before, gc.keep_snapshots)?;
+    info!(
+        "Removed {} packages without buildinfo, {} parse failures, {} snapshots and {} stored files",
+        stats.missing_buildinfos, stats.parse_failures, stats.snapshots, stats.blobs
+    );
+    Ok(())
+}
+
+/// Read the output of `export`, either the build groups or the request for rebuilderd
+fn read_groups(path: &str) -> Result<Vec<PkgGroup>> {
+    let buf = std::fs::read(path).with_context(|| anyhow!("Failed to read {:?}", path))?;
+    if let Ok(import) = serde_json::from_slice::<SuiteImport>(&buf) {
+        Ok(import.groups)
+    } else {
+        serde_json::from_slice(&buf)
+            .with_context(|| anyhow!("Failed to parse build groups from {:?}", path))
+    }
+}
+
+fn snapshot_groups(db: &Database, id: args::SnapshotId) -> Result<Vec<PkgGroup>> {
+    let (snapshot, _) = snapshot::open(db, id.id())?;
+    info!(
+        "Using snapshot #{} from {}",
+        snapshot.id, snapshot.created_at
+    );
+    snapshot::build_groups(db, &snapshot)
+}
 
-    info!("Writing final json...");
-    let mut stdout = std::io::stdout();
-    serde_json::to_writer_pretty(&mut stdout, &out).ok();
-    writeln!(stdout).ok();
+fn diff(db: &Database, diff: args::Diff) -> Result<()> {
+    let (old, new) = if diff.snapshots {
+        (
+            snapshot_groups(db, diff.old.parse()?)?,
+            snapshot_groups(db, diff.new.parse()?)?,
+        )
+    } else {
+        (read_groups(&diff.old)?, read_groups(&diff.new)?)
+    };
+
+    let changes = Diff::new(&old, &new);
+    if diff.json {
+        let mut stdout = io::stdout();
+        serde_json::to_writer_pretty(&mut stdout, &changes).ok();
+        writeln!(stdout).ok();
+    } else {
+        println!("{}", changes);
+    }
 
     Ok(())
 }
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let logging = match args.verbose {
+        0 => "info",
+        1 => "info,rebuilderd_debian_buildinfo_crawler=debug",
+        2 => "debug",
+        _ => "debug,rebuilderd_debian_buildinfo_crawler=trace",
+    };
+    env_logger::init_from_env(Env::default().default_filter_or(logging));
+
+    let db = Database::open(&args.database)?;
+
+    match args.subcommand {
+        SubCommand::Crawl(args) => crawl(&db, args).await,
+        SubCommand::Export(args) => export(&db, args).await,
+        SubCommand::Lookup(args) => lookup(&db, args),
+        SubCommand::Stats => stats(&db, &args.database),
+        SubCommand::Gc(args) => gc(&db, args),
+        SubCommand::Diff(args) => diff(&db, args),
+    }
+}
@@ -2,10 +2,16 @@
 use crate::errors::*;
 
 use diesel::sqlite::*;
+use diesel_migrations::MigrationConnection;
+use std::collections::HashSet;
 
 embed_migrations!();
 
-pub fn run(conn: &SqliteConnection) -> Result<()> {
+/// Run all missing migrations, returns the versions that were applied just now
+pub fn run(conn: &SqliteConnection) -> Result<HashSet<String>> {
+    diesel_migrations::setup_database(conn)?;
+    let before = conn.previously_run_migration_versions()?;
     embedded_migrations::run(conn)?;
-    Ok(())
+    let after = conn.previously_run_migration_versions()?;
+    Ok(after.difference(&before).cloned().collect())
 }
@@ -6,6 +6,8 @@ pub struct Artifact {
     pub id: i32,
     pub file_name: String,
     pub buildinfo_id: i32,
+    pub sha256: Option<String>,
+    pub size: Option<i64>,
 }
 
 #[derive(Insertable, Clone, PartialEq, Debug)]
@@ -13,4 +15,6 @@ pub struct Artifact {
 pub struct NewArtifact {
     pub file_name: String,
     pub buildinfo_id: i32,
+    pub sha256: Option<String>,
+    pub size: Option<i64>,
 }
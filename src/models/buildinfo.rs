@@ -6,6 +6,8 @@ pub struct Buildinfo {
     pub id: i32,
     pub url: String,
     pub content: String,
+    pub signer: Option<String>,
+    pub verified_with: Option<String>,
 }
 
 #[derive(Insertable, Clone, PartialEq, Debug)]
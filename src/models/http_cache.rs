@@ -0,0 +1,26 @@
+use crate::schema::*;
+
+#[derive(Identifiable, Queryable, AsChangeset, Clone, PartialEq, Debug)]
+#[table_name = "http_cache"]
+pub struct HttpCacheEntry {
+    pub id: i32,
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Small bodies like directory listings are stored inline
+    pub body: Option<Vec<u8>>,
+    /// The sha256 of the body in the blob store, for downloaded files
+    pub blob: Option<String>,
+}
+
+#[derive(Insertable, Clone, PartialEq, Debug)]
+#[table_name = "http_cache"]
+pub struct NewHttpCacheEntry {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Small bodies like directory listings are stored inline
+    pub body: Option<Vec<u8>>,
+    /// The sha256 of the body in the blob store, for downloaded files
+    pub blob: Option<String>,
+}
@@ -0,0 +1,21 @@
+use crate::schema::*;
+use chrono::NaiveDateTime;
+
+#[derive(Identifiable, Queryable, AsChangeset, Clone, PartialEq, Debug)]
+#[table_name = "missing_buildinfos"]
+pub struct MissingBuildinfo {
+    pub id: i32,
+    pub deb_folder: String,
+    pub file_name: String,
+    pub listing_hash: String,
+    pub checked_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Clone, PartialEq, Debug)]
+#[table_name = "missing_buildinfos"]
+pub struct NewMissingBuildinfo {
+    pub deb_folder: String,
+    pub file_name: String,
+    pub listing_hash: String,
+    pub checked_at: NaiveDateTime,
+}
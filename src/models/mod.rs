@@ -1,2 +1,7 @@
 pub mod artifact;
 pub mod buildinfo;
+pub mod http_cache;
+pub mod missing_buildinfo;
+pub mod parse_failure;
+pub mod release;
+pub mod snapshot;
@@ -0,0 +1,31 @@
+use crate::schema::*;
+use chrono::NaiveDateTime;
+
+#[derive(Identifiable, Queryable, AsChangeset, Clone, PartialEq, Debug)]
+#[table_name = "parse_failures"]
+pub struct ParseFailure {
+    pub id: i32,
+    pub url: String,
+    pub kind: String,
+    pub line: Option<i32>,
+    pub message: String,
+    pub failed_at: NaiveDateTime,
+    /// The modification time in the directory listing when the file failed to parse
+    pub listing_mtime: Option<NaiveDateTime>,
+    /// The size in the directory listing when the file failed to parse
+    pub listing_size: Option<i64>,
+}
+
+#[derive(Insertable, Clone, PartialEq, Debug)]
+#[table_name = "parse_failures"]
+pub struct NewParseFailure {
+    pub url: String,
+    pub kind: String,
+    pub line: Option<i32>,
+    pub message: String,
+    pub failed_at: NaiveDateTime,
+    /// The modification time in the directory listing when the file failed to parse
+    pub listing_mtime: Option<NaiveDateTime>,
+    /// The size in the directory listing when the file failed to parse
+    pub listing_size: Option<i64>,
+}
@@ -0,0 +1,26 @@
+use crate::schema::*;
+use chrono::NaiveDateTime;
+
+#[derive(Identifiable, Queryable, AsChangeset, Clone, PartialEq, Debug)]
+#[table_name = "releases"]
+pub struct Release {
+    pub id: i32,
+    pub url: String,
+    pub origin: Option<String>,
+    pub label: Option<String>,
+    pub suite: Option<String>,
+    pub codename: Option<String>,
+    pub date: Option<String>,
+    pub fetched_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Clone, PartialEq, Debug)]
+#[table_name = "releases"]
+pub struct NewRelease {
+    pub url: String,
+    pub origin: Option<String>,
+    pub label: Option<String>,
+    pub suite: Option<String>,
+    pub codename: Option<String>,
+    pub date: Option<String>,
+}
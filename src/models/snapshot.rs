@@ -0,0 +1,31 @@
+use crate::schema::*;
+use chrono::NaiveDateTime;
+
+#[derive(Identifiable, Queryable, AsChangeset, Clone, PartialEq, Debug)]
+#[table_name = "snapshots"]
+pub struct Snapshot {
+    pub id: i32,
+    pub created_at: NaiveDateTime,
+    /// The sha256 of the build groups generated from the snapshot, as json in the blob store.
+    /// Snapshots of older versions don't have them
+    pub build_groups: Option<String>,
+}
+
+#[derive(Identifiable, Queryable, AsChangeset, Clone, PartialEq, Debug)]
+#[table_name = "snapshot_indexes"]
+pub struct SnapshotIndex {
+    pub id: i32,
+    pub snapshot_id: i32,
+    pub url: String,
+    /// The sha256 of the index in the blob store
+    pub blob: String,
+}
+
+#[derive(Insertable, Clone, PartialEq, Debug)]
+#[table_name = "snapshot_indexes"]
+pub struct NewSnapshotIndex {
+    pub snapshot_id: i32,
+    pub url: String,
+    /// The sha256 of the index in the blob store
+    pub blob: String,
+}
@@ -0,0 +1,317 @@
+use crate::errors::*;
+use sha2::{Digest, Sha256};
+use std::io::prelude::*;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use tempfile::NamedTempFile;
+
+/// The first line of a clearsigned message
+pub const SIGNED_MESSAGE_HEADER: &str = "-----BEGIN PGP SIGNED MESSAGE-----";
+/// The first line of the signature of a clearsigned message
+pub const SIGNATURE_HEADER: &str = "-----BEGIN PGP SIGNATURE-----";
+/// The last line of a signature
+pub const SIGNATURE_FOOTER: &str = "-----END PGP SIGNATURE-----";
+const PUBLIC_KEY_HEADER: &str = "-----BEGIN PGP PUBLIC KEY BLOCK-----";
+
+/// The result of a signature check
+#[derive(Debug, PartialEq)]
+pub enum Verification {
+    /// The signature was made by a key of the keyring
+    Valid {
+        /// The fingerprint of the key that made the signature
+        fingerprint: String,
+    },
+    /// The data has no signature
+    Unsigned,
+    /// The signature couldn't be verified, with the reason reported by gpgv
+    Invalid(String),
+}
+
+/// An OpenPGP keyring, signatures are verified with `gpgv` the same way apt does it.
+///
+/// Clones share the same keyring file.
+#[derive(Clone)]
+pub struct Keyring {
+    file: Arc<NamedTempFile>,
+    id: String,
+}
+
+impl Keyring {
+    /// Read a keyring in binary or ascii-armored format from disk
+    pub fn open(path: &str) -> Result<Keyring> {
+        let content = std::fs::read(path)
+            .with_context(|| anyhow!("Failed to read keyring from disk: {:?}", path))?;
+
+        // gpgv only accepts binary keyrings
+        let content = if content.starts_with(PUBLIC_KEY_HEADER.as_bytes()) {
+            debug!("Dearmoring keyring {:?}", path);
+            let content = String::from_utf8(content).context("Armored keyring is not utf8")?;
+            dearmor(&content)?
+        } else {
+            content
+        };
+
+        let id = format!("{:x}", Sha256::digest(&content));
+        let mut file = NamedTempFile::new().context("Failed to create temporary keyring")?;
+        file.write_all(&content)?;
+        file.flush()?;
+        Ok(Keyring {
+            file: Arc::new(file),
+            id,
+        })
+    }
+
+    /// The sha256 of the keyring, verification results are only reused for the same keyring
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn gpgv(
+        &self,
+        args: &[&Path],
+        stdin: Option<&[u8]>,
+        output: Option<&Path>,
+    ) -> Result<Verification> {
+        let mut cmd = Command::new("gpgv");
+        cmd.arg("--keyring")
+            .arg(self.file.path())
+            .args(["--status-fd", "1"]);
+        if let Some(output) = output {
+            cmd.arg("--output").arg(output);
+        }
+        let mut child = cmd
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to execute gpgv")?;
+
+        let mut child_stdin = child.stdin.take().unwrap();
+        if let Some(stdin) = stdin {
+            child_stdin.write_all(stdin)?;
+        }
+        drop(child_stdin);
+
+        let output = child.wait_with_output()?;
+        let status = String::from_utf8_lossy(&output.stdout);
+        trace!("gpgv status output: {:?}", status);
+
+        match (output.status.success(), parse_status(&status)) {
+            (true, Some(fingerprint)) => Ok(Verification::Valid { fingerprint }),
+            _ => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                // the last line has the reason why verification failed
+                let reason = stderr.trim().lines().last().unwrap_or("gpgv failed");
+                Ok(Verification::Invalid(reason.to_string()))
+            }
+        }
+    }
+
+    /// Verify a clearsigned message, data that doesn't start with the signed message header is `Unsigned`
+    pub fn verify_clearsigned(&self, data: &[u8]) -> Result<Verification> {
+        if !data.starts_with(SIGNED_MESSAGE_HEADER.as_bytes()) {
+            return Ok(Verification::Unsigned);
+        }
+        self.gpgv(&[], Some(data), None)
+    }
+
+    /// Verify a clearsigned document and return the text that was signed, as extracted by gpgv.
+    ///
+    /// This is what apt does for InRelease files, the armor is never parsed by us.
+    pub fn verify_clearsigned_text(&self, data: &[u8]) -> Result<(Verification, Option<Vec<u8>>)> {
+        if !data.starts_with(SIGNED_MESSAGE_HEADER.as_bytes()) {
+            return Ok((Verification::Unsigned, None));
+        }
+
+        // gpgv refuses to overwrite existing files
+        let dir = tempfile::tempdir().context("Failed to create temporary directory")?;
+        let path = dir.path().join("signed");
+        let verification = self.gpgv(&[], Some(data), Some(&path))?;
+        let text = match verification {
+            Verification::Valid { .. } => {
+                Some(std::fs::read(&path).context("Failed to read signed text from gpgv output")?)
+            }
+            _ => None,
+        };
+        Ok((verification, text))
+    }
+
+    /// Verify a detached signature of `data`
+    pub fn verify_detached(&self, data: &[u8], signature: &[u8]) -> Result<Verification> {
+        let mut data_file = NamedTempFile::new()?;
+        data_file.write_all(data)?;
+        data_file.flush()?;
+
+        let mut sig_file = NamedTempFile::new()?;
+        sig_file.write_all(signature)?;
+        sig_file.flush()?;
+
+        self.gpgv(&[sig_file.path(), data_file.path()], None, None)
+    }
+}
+
+/// Get the primary key fingerprint of a valid signature from the gpgv status output
+fn parse_status(status: &str) -> Option<String> {
+    status.lines().find_map(|line| {
+        let line = line.strip_prefix("[GNUPG:] VALIDSIG ")?;
+        // the last field is the fingerprint of the primary key, the first one of the signing key
+        let fields = line.split(' ').collect::<Vec<_>>();
+        let fingerprint = if fields.len() >= 10 {
+            fields[9]
+        } else {
+            fields[0]
+        };
+        Some(fingerprint.to_string())
+    })
+}
+
+fn dearmor(s: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+
+    let mut data = String::new();
+    let mut in_body = false;
+    for line in s.lines() {
+        let line = line.trim_end();
+        if line == PUBLIC_KEY_HEADER {
+            data.clear();
+            in_body = false;
+        } else if line.starts_with("-----END ") {
+            out.extend(base64::decode(&data).context("Failed to decode armored keyring")?);
+            data.clear();
+        } else if !in_body {
+            // skip armor headers until the first empty line
+            in_body = line.is_empty();
+        } else if !line.starts_with('=') {
+            data.push_str(line);
+        }
+    }
+
+    if out.is_empty() {
+        bail!("Armored keyring doesn't contain any keys");
+    }
+
+    Ok(out)
+}
+
+/// Remove the OpenPGP armor of a clearsigned document, unsigned documents are returned as-is
+pub fn strip_signature(s: &str) -> String {
+    if !s.starts_with(SIGNED_MESSAGE_HEADER) {
+        return s.to_string();
+    }
+
+    let mut out = String::new();
+    // skip the armor headers until the first empty line
+    let mut lines = s.lines().skip_while(|line| !line.is_empty()).skip(1);
+    for line in &mut lines {
+        if line == SIGNATURE_HEADER {
+            break;
+        }
+        out.push_str(line.strip_prefix("- ").unwrap_or(line));
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_status_valid() {
+        let status = "[GNUPG:] NEWSIG
+[GNUPG:] KEY_CONSIDERED A07AA36D226F4D82454F3831BDC193A3E5E24BAC 0
+[GNUPG:] SIG_ID XhQDB19btKTuu0xMj9sxJPtZhWs 2026-10-18 1792294610
+[GNUPG:] KEY_CONSIDERED A07AA36D226F4D82454F3831BDC193A3E5E24BAC 0
+[GNUPG:] GOODSIG BDC193A3E5E24BAC Test <t@example.com>
+[GNUPG:] VALIDSIG 5C4B5AB4A3A4F2D1B8E1E1A2C1F2E3D4A5B6C7D8 2026-10-18 1792294610 0 4 0 22 8 01 A07AA36D226F4D82454F3831BDC193A3E5E24BAC
+";
+        assert_eq!(
+            parse_status(status).as_deref(),
+            Some("A07AA36D226F4D82454F3831BDC193A3E5E24BAC")
+        );
+    }
+
+    #[test]
+    fn parse_status_missing_key() {
+        let status = "[GNUPG:] NEWSIG
+[GNUPG:] ERRSIG BDC193A3E5E24BAC 22 8 01 1792294610 9 A07AA36D226F4D82454F3831BDC193A3E5E24BAC
+[GNUPG:] NO_PUBKEY BDC193A3E5E24BAC
+";
+        assert_eq!(parse_status(status), None);
+    }
+
+    #[test]
+    fn dearmor_keyring() {
+        let armored = "-----BEGIN PGP PUBLIC KEY BLOCK-----
+Comment: test
+
+mDMEaQYGYhYJKwYBBAHaRw8BAQdA
+=AAAA
+-----END PGP PUBLIC KEY BLOCK-----
+";
+        let data = dearmor(armored).unwrap();
+        assert_eq!(
+            data,
+            base64::decode("mDMEaQYGYhYJKwYBBAHaRw8BAQdA").unwrap()
+        );
+    }
+
+    #[test]
+    fn verify_clearsigned_text_with_gpgv() {
+        let home = tempfile::tempdir().unwrap();
+        let gpg = |args: &[&str], stdin: &[u8]| {
+            let mut child = Command::new("gpg")
+                .arg("--homedir")
+                .arg(home.path())
+                .args(["--batch", "--pinentry-mode", "loopback", "--passphrase", ""])
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+            child.stdin.take().unwrap().write_all(stdin).ok()?;
+            let output = child.wait_with_output().ok()?;
+            output.status.success().then_some(output.stdout)
+        };
+
+        // gpg is not a build dependency, skip if it's not installed
+        let key = gpg(&["--quick-gen-key", "Test <t@example.com>", "ed25519"], b"")
+            .and_then(|_| gpg(&["--export", "t@example.com"], b""));
+        let Some(key) = key else {
+            return;
+        };
+        let signed = gpg(&["--clearsign"], b"Origin: Debian\n").unwrap();
+
+        let mut keyring_file = NamedTempFile::new().unwrap();
+        keyring_file.write_all(&key).unwrap();
+        let keyring = Keyring::open(keyring_file.path().to_str().unwrap()).unwrap();
+
+        let (verification, text) = keyring.verify_clearsigned_text(&signed).unwrap();
+        assert!(matches!(verification, Verification::Valid { .. }));
+        assert_eq!(text.as_deref(), Some(&b"Origin: Debian\n"[..]));
+
+        let tampered = String::from_utf8(signed)
+            .unwrap()
+            .replace("Origin: Debian", "Origin: Evil");
+        let (verification, text) = keyring
+            .verify_clearsigned_text(tampered.as_bytes())
+            .unwrap();
+        assert!(matches!(verification, Verification::Invalid(_)));
+        assert_eq!(text, None);
+    }
+
+    #[test]
+    fn strip_unsigned() {
+        let data = "Origin: Debian\nSuite: unstable\n";
+        assert_eq!(strip_signature(data), data);
+    }
+
+    #[test]
+    fn strip_dash_escaped() {
+        let data = "-----BEGIN PGP SIGNED MESSAGE-----\nHash: SHA512\n\nFormat: 1.0\n- -----BEGIN FOO\n-----BEGIN PGP SIGNATURE-----\n\nAAAA\n-----END PGP SIGNATURE-----\n";
+        assert_eq!(strip_signature(data), "Format: 1.0\n-----BEGIN FOO\n");
+    }
+}
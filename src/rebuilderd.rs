@@ -0,0 +1,194 @@
+use crate::errors::*;
+use rebuilderd_common::api::{Client, SuiteImport};
+use rebuilderd_common::config::{self, ConfigFile};
+use reqwest::StatusCode;
+
+/// Client for the rebuilderd api, submits build groups like `rebuildctl pkgs sync-stdin` does
+pub struct Rebuilderd {
+    client: Client,
+    endpoint: String,
+}
+
+impl Rebuilderd {
+    /// Without an explicit auth cookie it's looked up the same way rebuildctl does, from
+    /// `REBUILDERD_COOKIE_PATH` or the `[endpoints]` section of rebuilderd.conf
+    pub fn new(endpoint: &str, auth_cookie: Option<String>) -> Result<Rebuilderd> {
+        let config = config::load(None::<&str>).context("Failed to load rebuilderd config")?;
+        Rebuilderd::with_config(config, endpoint, auth_cookie)
+    }
+
+    fn with_config(
+        config: ConfigFile,
+        endpoint: &str,
+        auth_cookie: Option<String>,
+    ) -> Result<Rebuilderd> {
+        let mut client = Client::new(config, Some(endpoint.to_string()))?;
+        if let Some(cookie) = auth_cookie {
+            client.auth_cookie(cookie);
+        } else {
+            client.with_auth_cookie()?;
+        }
+        Ok(Rebuilderd {
+            client,
+            endpoint: endpoint.to_string(),
+        })
+    }
+
+    /// Replace the packages of the suite in rebuilderd with the given build groups
+    pub async fn sync(&self, import: &SuiteImport) -> Result<()> {
+        info!(
+            "Submitting {} build groups for {}/{} to {:?}",
+            import.groups.len(),
+            import.distro,
+            import.suite,
+            self.endpoint
+        );
+
+        self.client.sync_suite(import).await.map_err(|err| {
+            let status = err
+                .downcast_ref::<reqwest::Error>()
+                .and_then(|err| err.status());
+            match status {
+                Some(status) => {
+                    let hint = match status {
+                        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                            " (is the auth cookie correct?)"
+                        }
+                        _ => "",
+                    };
+                    anyhow!(
+                        "Rebuilderd rejected the build groups with {}{}",
+                        status,
+                        hint
+                    )
+                }
+                None => err.context(format!(
+                    "Failed to send build groups to rebuilderd: {:?}",
+                    self.endpoint
+                )),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use rebuilderd_common::api::AUTH_COOKIE_HEADER;
+    use rebuilderd_common::config::EndpointConfig;
+    use rebuilderd_common::{PkgArtifact, PkgGroup};
+    use std::convert::Infallible;
+    use tokio::sync::mpsc;
+
+    // (path, auth cookie, body) of every request the mock server received
+    type Received = (String, Option<String>, Vec<u8>);
+
+    async fn mock_server(
+        status: StatusCode,
+        body: &'static str,
+    ) -> (String, mpsc::UnboundedReceiver<Received>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let make_svc = make_service_fn(move |_| {
+            let tx = tx.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let tx = tx.clone();
+                    async move {
+                        let path = req.uri().path().to_string();
+                        let cookie = req
+                            .headers()
+                            .get(AUTH_COOKIE_HEADER)
+                            .map(|v| v.to_str().unwrap().to_string());
+                        let data = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        tx.send((path, cookie, data.to_vec())).unwrap();
+
+                        let response = Response::builder()
+                            .status(status)
+                            .body(Body::from(body))
+                            .unwrap();
+                        Ok::<_, Infallible>(response)
+                    }
+                }))
+            }
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let url = format!("http://{}/", server.local_addr());
+        tokio::spawn(server);
+        (url, rx)
+    }
+
+    fn import() -> SuiteImport {
+        SuiteImport {
+            distro: "debian".to_string(),
+            suite: "main".to_string(),
+            groups: vec![PkgGroup {
+                name: "rust-sniffglue".to_string(),
+                version: "0.14.0-2".to_string(),
+                distro: "debian".to_string(),
+                suite: "main".to_string(),
+                architecture: "amd64".to_string(),
+                input_url: Some("https://buildinfos.debian.net/buildinfo-pool/r/rust-sniffglue/rust-sniffglue_0.14.0-2_amd64.buildinfo".to_string()),
+                artifacts: vec![PkgArtifact {
+                    name: "sniffglue".to_string(),
+                    version: "0.14.0-2".to_string(),
+                    url: "https://deb.debian.org/debian/pool/main/r/rust-sniffglue/sniffglue_0.14.0-2_amd64.deb".to_string(),
+                }],
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn sync_build_groups() {
+        let (url, mut received) = mock_server(StatusCode::OK, "\"Ok\"").await;
+        Rebuilderd::with_config(ConfigFile::default(), &url, Some("secret".to_string()))
+            .unwrap()
+            .sync(&import())
+            .await
+            .unwrap();
+
+        let (path, cookie, body) = received.recv().await.unwrap();
+        assert_eq!(path, "/api/v0/pkgs/sync");
+        assert_eq!(cookie.as_deref(), Some("secret"));
+        let body = serde_json::from_slice::<SuiteImport>(&body).unwrap();
+        assert_eq!(body.distro, "debian");
+        assert_eq!(body.suite, "main");
+        assert_eq!(body.groups, import().groups);
+    }
+
+    #[tokio::test]
+    async fn sync_with_cookie_from_config() {
+        let (url, mut received) = mock_server(StatusCode::OK, "\"Ok\"").await;
+        let mut config = ConfigFile::default();
+        config.endpoints.insert(
+            url.clone(),
+            EndpointConfig {
+                cookie: "from-config".to_string(),
+            },
+        );
+        Rebuilderd::with_config(config, &url, None)
+            .unwrap()
+            .sync(&import())
+            .await
+            .unwrap();
+
+        let (_, cookie, _) = received.recv().await.unwrap();
+        assert_eq!(cookie.as_deref(), Some("from-config"));
+    }
+
+    #[tokio::test]
+    async fn sync_rejected() {
+        let (url, _received) = mock_server(StatusCode::UNAUTHORIZED, "Not authorized\n").await;
+        let err = Rebuilderd::with_config(ConfigFile::default(), &url, Some("wrong".to_string()))
+            .unwrap()
+            .sync(&import())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Rebuilderd rejected the build groups with 401 Unauthorized (is the auth cookie correct?)"
+        );
+    }
+}
@@ -0,0 +1,413 @@
+use crate::compression::Compression;
+use crate::db::Database;
+use crate::deb::PackagesIndex;
+use crate::errors::*;
+use crate::http_cache;
+use crate::pgp::{self, Keyring, Verification};
+use crate::utils::{self, HttpClient};
+use std::str::FromStr;
+
+// Preferred order of compressions when selecting an index from the release file
+const INDEX_EXTENSIONS: &[&str] = &[".xz", ".zst", ".bz2", ".gz", ""];
+
+/// A parsed Release or InRelease file
+#[derive(Debug, PartialEq)]
+pub struct Release {
+    /// The `Origin` field, e.g. `Debian`
+    pub origin: Option<String>,
+    /// The `Label` field, e.g. `Debian`
+    pub label: Option<String>,
+    /// The `Suite` field, e.g. `unstable`
+    pub suite: Option<String>,
+    /// The `Codename` field, e.g. `sid`
+    pub codename: Option<String>,
+    /// The `Date` field, when the Release file was generated
+    pub date: Option<String>,
+    /// The architectures that are part of the release
+    pub architectures: Vec<String>,
+    /// The components that are part of the release, e.g. `main`
+    pub components: Vec<String>,
+    /// The files listed in the `SHA256` field
+    pub sha256: Vec<ReleaseFile>,
+}
+
+/// A file listed in the Release file, e.g. a Packages index
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReleaseFile {
+    /// The sha256 in hex
+    pub sha256: String,
+    /// The size in bytes
+    pub size: u64,
+    /// The path relative to the directory of the Release file
+    pub path: String,
+}
+
+impl ReleaseFile {
+    /// Make sure a downloaded file has the size and checksum listed in the release file
+    pub fn verify(&self, size: u64, sha256: &str) -> Result<()> {
+        if size != self.size {
+            bail!(
+                "Size mismatch for {:?}, expected {} bytes but got {}",
+                self.path,
+                self.size,
+                size
+            );
+        }
+
+        if sha256 != self.sha256 {
+            bail!(
+                "Checksum mismatch for {:?}, expected sha256 {:?} but got {:?}",
+                self.path,
+                self.sha256,
+                sha256
+            );
+        }
+
+        debug!("Verified {:?} with sha256 {:?}", self.path, self.sha256);
+        Ok(())
+    }
+}
+
+impl Release {
+    /// Look up a file by its path, relative to the directory of the Release file
+    pub fn find_file(&self, path: &str) -> Option<&ReleaseFile> {
+        self.sha256.iter().find(|f| f.path == path)
+    }
+
+    /// List the Packages indexes for this component and architecture, the best compression first
+    pub fn packages_indexes(&self, component: &str, arch: &str) -> Vec<&ReleaseFile> {
+        INDEX_EXTENSIONS
+            .iter()
+            .filter_map(|ext| {
+                let path = format!("{}/binary-{}/Packages{}", component, arch, ext);
+                self.find_file(&path)
+            })
+            .collect()
+    }
+}
+
+impl FromStr for Release {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut origin = None;
+        let mut label = None;
+        let mut suite = None;
+        let mut codename = None;
+        let mut date = None;
+        let mut architectures = Vec::new();
+        let mut components = Vec::new();
+        let mut sha256 = Vec::new();
+
+        let mut section = None;
+        for line in pgp::strip_signature(s).lines() {
+            if line.starts_with(' ') {
+                // we're inside of a section
+                if section.as_deref() == Some("SHA256") {
+                    let file = release_file_from_checksum_line(line)
+                        .with_context(|| anyhow!("Invalid checksum line: {:?}", line))?;
+                    sha256.push(file);
+                }
+            } else if let Some(s) = line.strip_suffix(':') {
+                section = Some(s.to_string());
+            } else if let Some((key, value)) = line.split_once(": ") {
+                section = None;
+                match key {
+                    "Origin" => origin = Some(value.to_string()),
+                    "Label" => label = Some(value.to_string()),
+                    "Suite" => suite = Some(value.to_string()),
+                    "Codename" => codename = Some(value.to_string()),
+                    "Date" => date = Some(value.to_string()),
+                    "Architectures" => {
+                        architectures = value.split_whitespace().map(String::from).collect()
+                    }
+                    "Components" => {
+                        components = value.split_whitespace().map(String::from).collect()
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(Release {
+            origin,
+            label,
+            suite,
+            codename,
+            date,
+            architectures,
+            components,
+            sha256,
+        })
+    }
+}
+
+fn release_file_from_checksum_line(line: &str) -> Option<ReleaseFile> {
+    let mut iter = line.split_whitespace();
+    let sha256 = iter.next()?.to_string();
+    let size = iter.next()?.parse().ok()?;
+    let path = iter.next()?.to_string();
+    Some(ReleaseFile { sha256, size, path })
+}
+
+/// The url of a file in the `dists/` directory of the mirror
+pub fn dists_url(source: &str, release: &str, path: &str) -> String {
+    format!(
+        "{}/dists/{}/{}",
+        source.trim_end_matches('/'),
+        release,
+        path
+    )
+}
+
+/// Download the InRelease file of a release, its signature is verified if a keyring is given
+pub async fn fetch_release(
+    client: &HttpClient,
+    source: &str,
+    release: &str,
+    keyring: Option<&Keyring>,
+) -> Result<(String, Release)> {
+    let url = dists_url(source, release, "InRelease");
+    let (url, content) = match utils::read_path_or_url(client, &url).await {
+        Ok(content) => {
+            if let Some(keyring) = keyring {
+                // only parse the text that was actually covered by the signature
+                let (verification, text) = keyring.verify_clearsigned_text(&content)?;
+                ensure_valid_signature(&url, verification)?;
+                let text = text.context("gpgv didn't output the signed text")?;
+                (url, text)
+            } else {
+                (url, content)
+            }
+        }
+        Err(err) => {
+            warn!(
+                "Failed to fetch InRelease file, trying Release instead: {:#}",
+                err
+            );
+            let url = dists_url(source, release, "Release");
+            let content = utils::read_path_or_url(client, &url).await?;
+            if let Some(keyring) = keyring {
+                let signature_url = dists_url(source, release, "Release.gpg");
+                let signature = utils::read_path_or_url(client, &signature_url).await?;
+                let verification = keyring.verify_detached(&content, &signature)?;
+                ensure_valid_signature(&url, verification)?;
+            }
+            (url, content)
+        }
+    };
+
+    let content = String::from_utf8(content).context("Release file is not valid utf8")?;
+    let release = content
+        .parse()
+        .with_context(|| anyhow!("Failed to parse release file: {:?}", url))?;
+    Ok((url, release))
+}
+
+fn ensure_valid_signature(url: &str, verification: Verification) -> Result<()> {
+    match verification {
+        Verification::Valid { fingerprint } => {
+            info!("Release file {:?} is signed by {:?}", url, fingerprint);
+            Ok(())
+        }
+        Verification::Unsigned => bail!("Release file {:?} is not signed", url),
+        Verification::Invalid(err) => {
+            bail!("Release file {:?} has invalid signature: {}", url, err)
+        }
+    }
+}
+
+/// Download a Packages index that is listed in the Release file and verify its checksum
+pub async fn open_packages_index(
+    client: &HttpClient,
+    db: &Database,
+    source: &str,
+    release_name: &str,
+    release: &Release,
+    component: &str,
+    arch: &str,
+) -> Result<PackagesIndex> {
+    let indexes = release.packages_indexes(component, arch);
+    if indexes.is_empty() {
+        bail!(
+            "Release file for {:?} has no Packages index for {}/{}",
+            release_name,
+            component,
+            arch
+        );
+    }
+
+    for index in indexes {
+        let url = dists_url(source, release_name, &index.path);
+        match http_cache::fetch_blob(client, db, &url).await {
+            Ok((blob, cache)) => {
+                if Compression::from_extension(&index.path).is_none() {
+                    info!("Using uncompressed package index: {:?}", url);
+                }
+                // the whole index is verified before anything of it is parsed
+                index
+                    .verify(blob.size, &blob.sha256)
+                    .with_context(|| anyhow!("Failed to download package index: {:?}", url))?;
+                return Ok(PackagesIndex {
+                    url,
+                    sha256: blob.sha256,
+                    cache,
+                });
+            }
+            Err(err) => warn!("Failed to fetch package index, trying next: {:#}", err),
+        }
+    }
+
+    bail!(
+        "Failed to download any Packages index for {}/{} in {:?}",
+        component,
+        arch,
+        release_name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IN_RELEASE: &str = r#"-----BEGIN PGP SIGNED MESSAGE-----
+Hash: SHA256
+
+Origin: Debian
+Label: Debian
+Suite: unstable
+Codename: sid
+Changelogs: https://metadata.ftp-master.debian.org/changelogs/@CHANGEPATH@_changelog
+Date: Sat, 29 Jan 2022 08:13:42 UTC
+Valid-Until: Sat, 05 Feb 2022 08:13:42 UTC
+Acquire-By-Hash: yes
+No-Support-for-Architecture-all: Packages
+Architectures: all amd64 arm64 armel armhf i386 mips64el mipsel ppc64el s390x
+Components: main contrib non-free
+Description: Debian x.y Unstable - Not Released
+MD5Sum:
+ 5a1e26ab4ee48d2eb3b0b8dcd4c0d2c9  1310392 contrib/Contents-all
+ d3b4d6d6e5e1ea3f1a8c0ab5d5f6a56b    89121 main/binary-amd64/Packages.gz
+SHA256:
+ a6b2a1bc5ce1e6ec53be3e8cdb9e0d3a94e1c4b2f1f6f9bcd2a5a0a2e3d4c5b6  1310392 contrib/Contents-all
+ 16a9a2e1c6e07b3c7c8fbb1d1d2e8b8b6a2c0b0a1d9e2f3a4b5c6d7e8f9a0b1c 45678901 main/binary-amd64/Packages
+ 3e4f1c9c4a1b2f0d2bb5a7e54f8a9c1d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b 11223344 main/binary-amd64/Packages.gz
+ eae1cb33d9fc46e6d8fda9c6cfc2d5a5c49cc6a1786f87c6fb1b6a4d9f7e9f11  8955528 main/binary-amd64/Packages.xz
+ 0f1e2d3c4b5a69788796a5b4c3d2e1f00f1e2d3c4b5a69788796a5b4c3d2e1f0   123456 contrib/binary-i386/Packages.gz
+-----BEGIN PGP SIGNATURE-----
+
+iQIzBAEBCAAdFiEEFukLP99l7eOqfzI8BO5yN7fUU+wFAmH1AyYACgkQBO5yN7fU
+U+yQ9w//ZsTTSqnbX1Rk8EYoCAy7mA8kVslWkq4v3gz8bTx5l6k0WcG6EytmdFuy
+=X8Ly
+-----END PGP SIGNATURE-----
+"#;
+
+    #[test]
+    fn parse_in_release() {
+        let release = IN_RELEASE.parse::<Release>().unwrap();
+        assert_eq!(release.origin.as_deref(), Some("Debian"));
+        assert_eq!(release.label.as_deref(), Some("Debian"));
+        assert_eq!(release.suite.as_deref(), Some("unstable"));
+        assert_eq!(release.codename.as_deref(), Some("sid"));
+        assert_eq!(
+            release.date.as_deref(),
+            Some("Sat, 29 Jan 2022 08:13:42 UTC")
+        );
+        assert_eq!(release.components, &["main", "contrib", "non-free"]);
+        assert_eq!(release.architectures.len(), 10);
+        assert_eq!(release.sha256.len(), 5);
+        assert_eq!(
+            release.find_file("main/binary-amd64/Packages.xz"),
+            Some(&ReleaseFile {
+                sha256: "eae1cb33d9fc46e6d8fda9c6cfc2d5a5c49cc6a1786f87c6fb1b6a4d9f7e9f11"
+                    .to_string(),
+                size: 8955528,
+                path: "main/binary-amd64/Packages.xz".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn select_packages_index() {
+        let release = IN_RELEASE.parse::<Release>().unwrap();
+        let indexes = release
+            .packages_indexes("main", "amd64")
+            .into_iter()
+            .map(|f| f.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            indexes,
+            &[
+                "main/binary-amd64/Packages.xz",
+                "main/binary-amd64/Packages.gz",
+                "main/binary-amd64/Packages",
+            ]
+        );
+
+        let indexes = release.packages_indexes("contrib", "i386");
+        assert_eq!(indexes.len(), 1);
+        assert_eq!(indexes[0].path, "contrib/binary-i386/Packages.gz");
+
+        assert!(release.packages_indexes("non-free", "amd64").is_empty());
+    }
+
+    #[tokio::test]
+    async fn reject_modified_packages_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dists/sid/main/binary-amd64");
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("Packages"), "Package: sniffglue\n").unwrap();
+
+        let release = Release {
+            origin: None,
+            label: None,
+            suite: None,
+            codename: None,
+            date: None,
+            architectures: vec!["amd64".to_string()],
+            components: vec!["main".to_string()],
+            sha256: vec![ReleaseFile {
+                sha256: "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+                    .to_string(),
+                size: 19,
+                path: "main/binary-amd64/Packages".to_string(),
+            }],
+        };
+        let db = Database::open(":memory:").unwrap();
+        let client = HttpClient::new(reqwest::Client::new());
+        let source = dir.path().to_str().unwrap();
+        let err = open_packages_index(&client, &db, source, "sid", &release, "main", "amd64")
+            .await
+            .err()
+            .unwrap();
+        assert!(format!("{:#}", err).contains("Checksum mismatch"));
+    }
+
+    #[test]
+    fn verify_release_file() {
+        let file = ReleaseFile {
+            sha256: "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08".to_string(),
+            size: 4,
+            path: "main/binary-amd64/Packages".to_string(),
+        };
+
+        assert!(file
+            .verify(
+                4,
+                "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+            )
+            .is_ok());
+        assert!(file
+            .verify(
+                4,
+                "0000000000000000000000000000000000000000000000000000000000000000"
+            )
+            .is_err());
+        assert!(file
+            .verify(
+                5,
+                "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+            )
+            .is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use crate::deb::DebianBinaryPkg;
+use crate::errors::*;
+use crate::models::parse_failure::ParseFailure;
+use serde::Serialize;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
+
+/// Why a binary package ended up without a buildinfo file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissingReason {
+    /// None of the buildinfo files in the listing reference the package
+    NoBuildinfo,
+    /// Buildinfo files reference the filename, but none of them with the right checksum
+    ChecksumMismatch,
+    /// The buildinfo directory listing of the source package couldn't be fetched
+    ListingFailed,
+    /// Some of the buildinfo files in the listing couldn't be downloaded, the package may be listed
+    /// in one of them
+    DownloadFailed,
+    /// The buildinfo file has no valid signature and --exclude-unsigned is set
+    Unsigned,
+    /// The buildinfo file that lists the package is in the database, but fails to parse now
+    ParseFailed,
+}
+
+/// A listing or buildinfo file that couldn't be downloaded, or a buildinfo file without valid
+/// signature
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FailedUrl {
+    /// The url of the file
+    pub url: String,
+    /// The error message
+    pub reason: String,
+}
+
+/// A buildinfo file that couldn't be parsed
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FailedParse {
+    /// The url of the buildinfo file
+    pub url: String,
+    /// The kind of error, e.g. `missing-field`
+    pub kind: String,
+    /// The line the error was found at, if known
+    pub line: Option<i32>,
+    /// The error message
+    pub reason: String,
+}
+
+impl From<ParseFailure> for FailedParse {
+    fn from(failure: ParseFailure) -> FailedParse {
+        FailedParse {
+            url: failure.url,
+            kind: failure.kind,
+            line: failure.line,
+            reason: failure.message,
+        }
+    }
+}
+
+/// A binary package that ended up without a buildinfo file
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MissingPackage {
+    /// The name of the binary package
+    pub name: String,
+    /// The version of the binary package
+    pub version: String,
+    /// The architecture of the binary package
+    pub architecture: String,
+    /// The name of the source package
+    pub source: String,
+    /// The url of the .deb
+    pub url: String,
+    /// Why no buildinfo file was found
+    pub reason: MissingReason,
+}
+
+/// Everything that went wrong during a run, written with --report for triage
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Report {
+    /// Buildinfo directory listings that couldn't be fetched
+    pub failed_listings: Vec<FailedUrl>,
+    /// Buildinfo files that couldn't be downloaded
+    pub failed_downloads: Vec<FailedUrl>,
+    /// Buildinfo files that failed to parse, including the ones from earlier runs
+    pub failed_parses: Vec<FailedParse>,
+    /// Buildinfo files without a valid signature, only checked with a keyring. They're still
+    /// used unless --exclude-unsigned is set
+    pub unsigned_buildinfos: Vec<FailedUrl>,
+    /// Binary packages that ended up without a buildinfo file
+    pub missing_buildinfo: Vec<MissingPackage>,
+}
+
+impl Report {
+    /// Add a package without buildinfo file, `url` is the url of the .deb
+    pub fn add_missing(&mut self, pkg: &DebianBinaryPkg, url: String, reason: MissingReason) {
+        self.missing_buildinfo.push(MissingPackage {
+            name: pkg.name.clone(),
+            version: pkg.version.clone(),
+            architecture: pkg.architecture.clone(),
+            source: pkg.source.name.clone(),
+            url,
+            reason,
+        });
+    }
+
+    /// Sort all lists so reports of different runs can be diffed.
+    ///
+    /// A listing is fetched once for every binary package of the source package, duplicates are removed.
+    pub fn sort(&mut self) {
+        for list in [
+            &mut self.failed_listings,
+            &mut self.failed_downloads,
+            &mut self.unsigned_buildinfos,
+        ] {
+            list.sort_by(|a, b| a.url.cmp(&b.url));
+            list.dedup_by(|a, b| a.url == b.url);
+        }
+        self.failed_parses.sort_by(|a, b| a.url.cmp(&b.url));
+        self.missing_buildinfo.sort_by(|a, b| {
+            (&a.source, &a.name, &a.architecture).cmp(&(&b.source, &b.name, &b.architecture))
+        });
+    }
+
+    /// Write the report as json
+    pub fn write(&self, path: &str) -> Result<()> {
+        let file =
+            File::create(path).with_context(|| anyhow!("Failed to create report: {:?}", path))?;
+        let mut w = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut w, self)?;
+        writeln!(w)?;
+        w.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deb::DebianSource;
+
+    #[test]
+    fn serialize_report() {
+        let pkg = DebianBinaryPkg {
+            name: "sniffglue".to_string(),
+            source: DebianSource {
+                name: "rust-sniffglue".to_string(),
+                version: None,
+            },
+            version: "0.14.0-2".to_string(),
+            architecture: "amd64".to_string(),
+            ..Default::default()
+        };
+
+        let mut report = Report::default();
+        report.failed_listings.push(FailedUrl {
+            url: "https://buildinfos.debian.net/buildinfo-pool/r/rust-sniffglue/".to_string(),
+            reason: "HTTP status client error (404 Not Found)".to_string(),
+        });
+        report.add_missing(
+            &pkg,
+            "https://deb.debian.org/debian/pool/main/r/rust-sniffglue/sniffglue_0.14.0-2_amd64.deb"
+                .to_string(),
+            MissingReason::ListingFailed,
+        );
+        report.unsigned_buildinfos.push(FailedUrl {
+            url: "https://buildinfos.debian.net/buildinfo-pool/r/rust-sniffglue/rust-sniffglue_0.14.0-2_amd64.buildinfo".to_string(),
+            reason: "Not signed".to_string(),
+        });
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "failed_listings": [{
+                    "url": "https://buildinfos.debian.net/buildinfo-pool/r/rust-sniffglue/",
+                    "reason": "HTTP status client error (404 Not Found)",
+                }],
+                "failed_downloads": [],
+                "failed_parses": [],
+                "unsigned_buildinfos": [{
+                    "url": "https://buildinfos.debian.net/buildinfo-pool/r/rust-sniffglue/rust-sniffglue_0.14.0-2_amd64.buildinfo",
+                    "reason": "Not signed",
+                }],
+                "missing_buildinfo": [{
+                    "name": "sniffglue",
+                    "version": "0.14.0-2",
+                    "architecture": "amd64",
+                    "source": "rust-sniffglue",
+                    "url": "https://deb.debian.org/debian/pool/main/r/rust-sniffglue/sniffglue_0.14.0-2_amd64.deb",
+                    "reason": "listing-failed",
+                }],
+            })
+        );
+    }
+}
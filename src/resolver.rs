@@ -0,0 +1,587 @@
+use crate::buildinfo;
+use crate::db::Database;
+use crate::deb::DebianBinaryPkg;
+use crate::errors::*;
+use crate::html::{self, ListingEntry};
+use crate::models::buildinfo::Buildinfo;
+use crate::models::missing_buildinfo::NewMissingBuildinfo;
+use crate::models::parse_failure::ParseFailure;
+use crate::report::{FailedUrl, Report};
+use crate::utils::{self, HttpClient};
+use crate::version::Version;
+use chrono::{NaiveDateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::OwnedMutexGuard;
+
+type FolderLocks = Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>;
+
+/// The result of looking up the buildinfo file of a binary package
+#[derive(Debug)]
+pub enum Resolved {
+    /// The buildinfo file that lists the package with the right checksum
+    Found(Buildinfo),
+    /// None of the buildinfo files in the listing reference the package
+    Missing,
+    /// Buildinfo files reference this filename, but none of them with the right checksum
+    ChecksumMismatch,
+    /// The buildinfo directory listing of the source package couldn't be fetched
+    ListingFailed,
+    /// Some of the buildinfo files in the listing couldn't be downloaded
+    DownloadFailed,
+}
+
+/// Looks up the buildinfo file for binary packages, crawling the buildinfo pool if needed.
+///
+/// Multiple lookups can run concurrently on the same task, only one lookup per source
+/// package is crawling the pool at a time.
+pub struct Resolver<'a> {
+    db: &'a Database,
+    client: &'a HttpClient,
+    buildinfo_base: String,
+    buildinfo_mirror: Option<String>,
+    skip_crawl: bool,
+    recheck_after: Duration,
+    strict: bool,
+    folder_locks: FolderLocks,
+    report: Mutex<Report>,
+}
+
+// Holds the lock of a folder in the buildinfo pool. The lock is removed from the map once the
+// last lookup that waited for it is done, so the map only grows with the concurrency
+struct FolderGuard<'r> {
+    locks: &'r FolderLocks,
+    deb_folder: String,
+    lock: Arc<tokio::sync::Mutex<()>>,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for FolderGuard<'_> {
+    fn drop(&mut self) {
+        self.guard.take();
+        let mut locks = self.locks.lock().unwrap();
+        // new references are only handed out while the map is locked
+        if Arc::strong_count(&self.lock) == 2 {
+            locks.remove(&self.deb_folder);
+        }
+    }
+}
+
+impl<'a> Resolver<'a> {
+    /// With `skip_crawl` only buildinfo files that are already in the database are used
+    pub fn new(
+        db: &'a Database,
+        client: &'a HttpClient,
+        buildinfo_base: String,
+        skip_crawl: bool,
+        recheck_after: Duration,
+        strict: bool,
+    ) -> Self {
+        Resolver {
+            db,
+            client,
+            buildinfo_base,
+            buildinfo_mirror: None,
+            skip_crawl,
+            recheck_after,
+            strict,
+            folder_locks: Mutex::new(HashMap::new()),
+            report: Mutex::new(Report::default()),
+        }
+    }
+
+    /// Read the buildinfo files from a local directory or http mirror with the same layout, they
+    /// are still stored with their url below `buildinfo_base`
+    pub fn buildinfo_mirror(mut self, mirror: Option<String>) -> Self {
+        self.buildinfo_mirror = mirror;
+        self
+    }
+
+    /// The listings and buildinfo files that failed to download so far
+    pub fn into_report(self) -> Report {
+        self.report.into_inner().unwrap()
+    }
+
+    /// The url of the directory with the buildinfo files of a source package
+    pub fn buildinfo_pool_url(&self, deb_folder: &str) -> String {
+        pool_url(&self.buildinfo_base, deb_folder)
+    }
+
+    // where the buildinfo files are actually read from
+    fn mirror_pool_url(&self, deb_folder: &str) -> String {
+        let base = self
+            .buildinfo_mirror
+            .as_ref()
+            .unwrap_or(&self.buildinfo_base);
+        pool_url(base, deb_folder)
+    }
+
+    fn lookup(&self, pkg: &DebianBinaryPkg) -> Result<Option<Buildinfo>> {
+        self.db.buildinfo_for_artifact(
+            &pkg.file_name,
+            pkg.sha256.as_deref(),
+            &pkg.buildinfo_version(),
+        )
+    }
+
+    fn recheck_due(&self, checked_at: NaiveDateTime) -> bool {
+        let age = Utc::now().naive_utc() - checked_at;
+        age.to_std()
+            .map(|age| age >= self.recheck_after)
+            .unwrap_or(false)
+    }
+
+    fn parse_failed(
+        &self,
+        url: &str,
+        entry: &ListingEntry,
+        err: &buildinfo::ParseError,
+    ) -> Result<()> {
+        self.db.add_parse_failure(url, Some(entry), err)?;
+        if self.strict {
+            bail!("Failed to parse buildinfo file {:?}: {}", url, err);
+        }
+        warn!(
+            "Failed to parse buildinfo file {:?}, skipping: {}",
+            url, err
+        );
+        Ok(())
+    }
+
+    async fn lock_folder(&self, deb_folder: &str) -> FolderGuard<'_> {
+        let lock = {
+            let mut locks = self.folder_locks.lock().unwrap();
+            locks.entry(deb_folder.to_string()).or_default().clone()
+        };
+        let mut folder = FolderGuard {
+            locks: &self.folder_locks,
+            deb_folder: deb_folder.to_string(),
+            lock: lock.clone(),
+            guard: None,
+        };
+        folder.guard = Some(lock.lock_owned().await);
+        folder
+    }
+
+    /// Find the buildinfo file of a binary package, crawling the buildinfo pool if it's not in the database yet
+    pub async fn resolve(&self, pkg: &DebianBinaryPkg) -> Result<Resolved> {
+        if let Some(buildinfo) = self.lookup(pkg)? {
+            debug!("Found buildinfo for {:?} in database", pkg.file_name);
+            debug!("Found buildinfo for {:?}: {:?}", pkg.file_name, buildinfo);
+            return Ok(Resolved::Found(buildinfo));
+        }
+
+        info!("Missing buildinfo for pkg: {:?}", pkg);
+        if !self.skip_crawl {
+            let _folder = self.lock_folder(&pkg.deb_folder).await;
+
+            // another lookup may have added it while we were waiting
+            if let Some(buildinfo) = self.lookup(pkg)? {
+                debug!(
+                    "Buildinfo for {:?} was added in the meantime",
+                    pkg.file_name
+                );
+                return Ok(Resolved::Found(buildinfo));
+            }
+
+            match self.crawl(pkg).await? {
+                Resolved::Missing => (),
+                resolved => return Ok(resolved),
+            }
+        }
+
+        if pkg.sha256.is_some() && self.db.artifact_filename_known(&pkg.file_name)? {
+            warn!(
+                "Found buildinfo for {:?} but none of them has a matching checksum",
+                pkg.file_name
+            );
+            Ok(Resolved::ChecksumMismatch)
+        } else {
+            warn!("Downloaded all buildinfos but none of them referenced our package");
+            Ok(Resolved::Missing)
+        }
+    }
+
+    async fn crawl(&self, pkg: &DebianBinaryPkg) -> Result<Resolved> {
+        let url = self.buildinfo_pool_url(&pkg.deb_folder);
+        let mirror_url = self.mirror_pool_url(&pkg.deb_folder);
+        info!("Syncing buildinfos for source pkg: {:?}", mirror_url);
+
+        let listing = html::fetch_buildinfo_listing(self.client, self.db, &mirror_url).await;
+        let mut buildinfos = match listing {
+            Ok(buildinfos) => buildinfos,
+            Err(err) => {
+                warn!("Failed to fetch buildinfo directory listing: {:#}", err);
+                self.report.lock().unwrap().failed_listings.push(FailedUrl {
+                    url: mirror_url,
+                    reason: format!("{:#}", err),
+                });
+                return Ok(Resolved::ListingFailed);
+            }
+        };
+
+        let listing_hash = listing_hash(&buildinfos);
+        if let Some(missing) = self.db.missing_buildinfo(&pkg.deb_folder, &pkg.file_name)? {
+            if missing.listing_hash == listing_hash && !self.recheck_due(missing.checked_at) {
+                debug!(
+                    "Directory listing didn't change since {}, skipping",
+                    missing.checked_at
+                );
+                return Ok(Resolved::Missing);
+            }
+        }
+
+        order_candidates(&mut buildinfos, pkg);
+
+        // if a candidate couldn't be checked, the package isn't known to be missing
+        let mut download_failed = false;
+        let mut parse_failed = false;
+        for entry in buildinfos {
+            // files are always stored with their public url, no matter where they're read from
+            let url = format!("{}{}", url, entry.href);
+            let mirror_url = format!("{}{}", mirror_url, entry.href);
+
+            if self.db.buildinfo_url_cache(&url)?.is_some() {
+                continue;
+            }
+            if let Some(failure) = self.db.parse_failure(&url)? {
+                if is_unchanged(&failure, &entry) {
+                    if self.strict {
+                        bail!(
+                            "Failed to parse buildinfo file {:?} in an earlier run: {}",
+                            url,
+                            failure.message
+                        );
+                    }
+                    debug!("Buildinfo file {:?} failed to parse before, skipping", url);
+                    continue;
+                }
+            }
+
+            // Download buildinfo file
+            let buildinfo = match utils::read_path_or_url(self.client, &mirror_url).await {
+                Ok(buildinfo) => buildinfo,
+                Err(err) => {
+                    warn!("Failed to download buildinfo file, skipping: {:#}", err);
+                    self.report
+                        .lock()
+                        .unwrap()
+                        .failed_downloads
+                        .push(FailedUrl {
+                            url: mirror_url,
+                            reason: format!("{:#}", err),
+                        });
+                    download_failed = true;
+                    continue;
+                }
+            };
+            let parsed = match buildinfo::Buildinfo::from_bytes(&buildinfo) {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    self.parse_failed(&url, &entry, &err)?;
+                    parse_failed = true;
+                    continue;
+                }
+            };
+            let buildinfo = String::from_utf8(buildinfo)?;
+
+            info!("Adding to cache for {:?}", url);
+            let artifacts = self.db.add_buildinfo(url, buildinfo, &parsed)?;
+            if artifacts.contains(&pkg.file_name) {
+                debug!("Buildinfo contained artifact we're looking for");
+                // the filename may be ambiguous, ensure the checksum matches
+                if let Some(buildinfo) = self.lookup(pkg)? {
+                    self.db
+                        .remove_missing_buildinfo(&pkg.deb_folder, &pkg.file_name)?;
+                    info!("Finished syncing buildinfos");
+                    return Ok(Resolved::Found(buildinfo));
+                }
+                debug!("Buildinfo has a different checksum for our artifact, continuing");
+            }
+        }
+
+        if download_failed {
+            return Ok(Resolved::DownloadFailed);
+        }
+        if parse_failed {
+            debug!("Not all buildinfo files could be checked, not caching the missing buildinfo");
+            return Ok(Resolved::Missing);
+        }
+
+        self.db.set_missing_buildinfo(NewMissingBuildinfo {
+            deb_folder: pkg.deb_folder.clone(),
+            file_name: pkg.file_name.clone(),
+            listing_hash,
+            checked_at: Utc::now().naive_utc(),
+        })?;
+
+        Ok(Resolved::Missing)
+    }
+}
+
+fn pool_url(base: &str, deb_folder: &str) -> String {
+    format!(
+        "{}/buildinfo-pool/{}/",
+        base.trim_end_matches('/'),
+        deb_folder
+    )
+}
+
+/// The file didn't change since it failed to parse, going by the directory listing
+fn is_unchanged(failure: &ParseFailure, entry: &ListingEntry) -> bool {
+    (entry.mtime.is_some() || entry.size.is_some())
+        && failure.listing_mtime == entry.mtime
+        && failure.listing_size == entry.size.map(|size| size as i64)
+}
+
+fn listing_hash(entries: &[ListingEntry]) -> String {
+    let mut hasher = Sha256::new();
+    for entry in entries {
+        hasher.update(entry.href.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Move the buildinfo files that most likely list the package to the front.
+///
+/// Files with the exact binary version (including binNMU) come first, then the source version, then everything
+/// else with the newest version first. Within a version the package architecture and the
+/// newest upload are preferred.
+fn order_candidates(entries: &mut [ListingEntry], pkg: &DebianBinaryPkg) {
+    let parse = |version: String| version.parse::<Version>().ok().map(|v| v.without_epoch());
+    let binary_version = parse(pkg.buildinfo_version());
+    let source_version = parse(pkg.source_version());
+
+    entries.sort_by_cached_key(|entry| {
+        let version = entry.version().and_then(|v| v.parse::<Version>().ok());
+        let version_rank = match &version {
+            Some(v) if Some(v) == binary_version.as_ref() => 0,
+            Some(v) if Some(v) == source_version.as_ref() => 1,
+            _ => 2,
+        };
+        let arch_rank = match entry.architecture() {
+            Some(arch) if arch == pkg.architecture => 0,
+            // source-only uploads don't list any binary packages
+            Some(arch) if arch.contains("source") => 2,
+            _ => 1,
+        };
+        (
+            version_rank,
+            Reverse(version),
+            arch_rank,
+            Reverse(entry.mtime),
+        )
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deb::DebianSource;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    fn entry(href: &str, mtime: &str) -> ListingEntry {
+        ListingEntry {
+            href: href.to_string(),
+            mtime: NaiveDateTime::parse_from_str(mtime, "%Y-%m-%d %H:%M").ok(),
+            size: None,
+        }
+    }
+
+    #[test]
+    fn test_order_candidates() {
+        let mut entries = vec![
+            entry("rust-sniffglue_0.9.1-1_amd64.buildinfo", "2019-06-01 10:00"),
+            entry(
+                "rust-sniffglue_0.14.0-1_amd64.buildinfo",
+                "2021-11-01 10:00",
+            ),
+            entry(
+                "rust-sniffglue_0.14.0-2_amd64.buildinfo",
+                "2021-12-06 21:06",
+            ),
+            entry(
+                "rust-sniffglue_0.14.0-2_arm64.buildinfo",
+                "2021-12-06 21:37",
+            ),
+            entry(
+                "rust-sniffglue_0.14.0-2_source.buildinfo",
+                "2021-12-06 21:06",
+            ),
+            entry(
+                "rust-sniffglue_0.14.0-2+b1_amd64.buildinfo",
+                "2022-01-10 08:00",
+            ),
+            entry(
+                "rust-sniffglue_0.15.0-1_amd64.buildinfo",
+                "2022-03-01 10:00",
+            ),
+        ];
+        let pkg = DebianBinaryPkg {
+            name: "sniffglue".to_string(),
+            source: DebianSource {
+                name: "rust-sniffglue".to_string(),
+                version: Some("0.14.0-2".to_string()),
+            },
+            version: "0.14.0-2+b1".to_string(),
+            architecture: "amd64".to_string(),
+            ..Default::default()
+        };
+        order_candidates(&mut entries, &pkg);
+
+        let hrefs = entries.iter().map(|e| e.href.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            hrefs,
+            &[
+                "rust-sniffglue_0.14.0-2+b1_amd64.buildinfo",
+                "rust-sniffglue_0.14.0-2_amd64.buildinfo",
+                "rust-sniffglue_0.14.0-2_arm64.buildinfo",
+                "rust-sniffglue_0.14.0-2_source.buildinfo",
+                "rust-sniffglue_0.15.0-1_amd64.buildinfo",
+                "rust-sniffglue_0.14.0-1_amd64.buildinfo",
+                "rust-sniffglue_0.9.1-1_amd64.buildinfo",
+            ]
+        );
+    }
+
+    fn sniffglue() -> DebianBinaryPkg {
+        DebianBinaryPkg {
+            name: "sniffglue".to_string(),
+            source: DebianSource {
+                name: "rust-sniffglue".to_string(),
+                version: None,
+            },
+            version: "0.14.0-2".to_string(),
+            architecture: "amd64".to_string(),
+            deb_folder: "r/rust-sniffglue".to_string(),
+            file_name: "sniffglue_0.14.0-2_amd64.deb".to_string(),
+            ..Default::default()
+        }
+    }
+
+    // a local buildinfo mirror, the listed file is a symlink to `target`
+    fn mirror(target: &Path) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("buildinfo-pool/r/rust-sniffglue");
+        fs::create_dir_all(&folder).unwrap();
+        std::os::unix::fs::symlink(
+            target,
+            folder.join("rust-sniffglue_0.14.0-2_amd64.buildinfo"),
+        )
+        .unwrap();
+        dir
+    }
+
+    fn resolver<'a>(db: &'a Database, client: &'a HttpClient, dir: &TempDir) -> Resolver<'a> {
+        Resolver::new(
+            db,
+            client,
+            "https://buildinfos.debian.net/".to_string(),
+            false,
+            Duration::from_secs(7 * 24 * 3600),
+            false,
+        )
+        .buildinfo_mirror(Some(dir.path().to_str().unwrap().to_string()))
+    }
+
+    #[tokio::test]
+    async fn local_mirror_stores_public_url() {
+        let target = tempfile::NamedTempFile::new().unwrap();
+        fs::write(
+            target.path(),
+            "Format: 1.0
+Source: rust-sniffglue
+Binary: sniffglue
+Architecture: amd64
+Version: 0.14.0-2
+Checksums-Sha256:
+ 448c781a9e594227bc9f0d6c65b8beba2b3add68d3583020de188d4cfa365b40 732980 sniffglue_0.14.0-2_amd64.deb
+",
+        )
+        .unwrap();
+        let dir = mirror(target.path());
+        let db = Database::open(":memory:").unwrap();
+        let client = HttpClient::new(reqwest::Client::new());
+
+        // the url of the buildinfo file becomes the `input_url` of the build group
+        let url = "https://buildinfos.debian.net/buildinfo-pool/r/rust-sniffglue/rust-sniffglue_0.14.0-2_amd64.buildinfo";
+        match resolver(&db, &client, &dir)
+            .resolve(&sniffglue())
+            .await
+            .unwrap()
+        {
+            Resolved::Found(buildinfo) => assert_eq!(buildinfo.url, url),
+            resolved => panic!("Unexpected result: {:?}", resolved),
+        }
+        assert!(db.buildinfo_url_cache(url).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn failed_download_is_not_cached_as_missing() {
+        // the file is listed but can't be read
+        let dir = mirror(Path::new("/does-not-exist"));
+        let db = Database::open(":memory:").unwrap();
+        let client = HttpClient::new(reqwest::Client::new());
+        let resolver = resolver(&db, &client, &dir);
+        let pkg = sniffglue();
+
+        let resolved = resolver.resolve(&pkg).await.unwrap();
+        assert!(matches!(resolved, Resolved::DownloadFailed));
+        assert_eq!(
+            db.missing_buildinfo(&pkg.deb_folder, &pkg.file_name)
+                .unwrap(),
+            None
+        );
+        assert_eq!(resolver.into_report().failed_downloads.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn unchanged_parse_failure_is_not_downloaded_again() {
+        let target = tempfile::NamedTempFile::new().unwrap();
+        fs::write(target.path(), "this is not a buildinfo file\n").unwrap();
+        let dir = mirror(target.path());
+        let db = Database::open(":memory:").unwrap();
+        let client = HttpClient::new(reqwest::Client::new());
+        let pkg = sniffglue();
+
+        let resolver1 = resolver(&db, &client, &dir);
+        resolver1.resolve(&pkg).await.unwrap();
+        assert_eq!(db.parse_failures().unwrap().len(), 1);
+
+        // the listing entry didn't change, reading the file would fail now
+        target.close().unwrap();
+        let resolver2 = resolver(&db, &client, &dir);
+        resolver2.resolve(&pkg).await.unwrap();
+        assert!(resolver2.into_report().failed_downloads.is_empty());
+        assert!(db
+            .missing_buildinfo(&pkg.deb_folder, &pkg.file_name)
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn folder_locks_are_removed() {
+        let dir = mirror(Path::new("/does-not-exist"));
+        let db = Database::open(":memory:").unwrap();
+        let client = HttpClient::new(reqwest::Client::new());
+        let resolver = resolver(&db, &client, &dir);
+        let pkg = sniffglue();
+        let dbgsym = DebianBinaryPkg {
+            name: "sniffglue-dbgsym".to_string(),
+            file_name: "sniffglue-dbgsym_0.14.0-2_amd64.deb".to_string(),
+            ..sniffglue()
+        };
+
+        // both wait for the same lock, the second one releases it
+        let (a, b) = futures::join!(resolver.resolve(&pkg), resolver.resolve(&dbgsym));
+        a.unwrap();
+        b.unwrap();
+        assert!(resolver.folder_locks.lock().unwrap().is_empty());
+    }
+}
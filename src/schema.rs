@@ -3,6 +3,8 @@ table! {
         id -> Integer,
         file_name -> Text,
         buildinfo_id -> Integer,
+        sha256 -> Nullable<Text>,
+        size -> Nullable<BigInt>,
     }
 }
 
@@ -11,12 +13,85 @@ table! {
         id -> Integer,
         url -> Text,
         content -> Text,
+        signer -> Nullable<Text>,
+        verified_with -> Nullable<Text>,
+    }
+}
+
+table! {
+    http_cache (id) {
+        id -> Integer,
+        url -> Text,
+        etag -> Nullable<Text>,
+        last_modified -> Nullable<Text>,
+        body -> Nullable<Binary>,
+        blob -> Nullable<Text>,
+    }
+}
+
+table! {
+    missing_buildinfos (id) {
+        id -> Integer,
+        deb_folder -> Text,
+        file_name -> Text,
+        listing_hash -> Text,
+        checked_at -> Timestamp,
+    }
+}
+
+table! {
+    parse_failures (id) {
+        id -> Integer,
+        url -> Text,
+        kind -> Text,
+        line -> Nullable<Integer>,
+        message -> Text,
+        failed_at -> Timestamp,
+        listing_mtime -> Nullable<Timestamp>,
+        listing_size -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    releases (id) {
+        id -> Integer,
+        url -> Text,
+        origin -> Nullable<Text>,
+        label -> Nullable<Text>,
+        suite -> Nullable<Text>,
+        codename -> Nullable<Text>,
+        date -> Nullable<Text>,
+        fetched_at -> Timestamp,
+    }
+}
+
+table! {
+    snapshot_indexes (id) {
+        id -> Integer,
+        snapshot_id -> Integer,
+        url -> Text,
+        blob -> Text,
+    }
+}
+
+table! {
+    snapshots (id) {
+        id -> Integer,
+        created_at -> Timestamp,
+        build_groups -> Nullable<Text>,
     }
 }
 
 joinable!(artifacts -> buildinfos (buildinfo_id));
+joinable!(snapshot_indexes -> snapshots (snapshot_id));
 
 allow_tables_to_appear_in_same_query!(
     artifacts,
     buildinfos,
+    http_cache,
+    missing_buildinfos,
+    parse_failures,
+    releases,
+    snapshot_indexes,
+    snapshots,
 );
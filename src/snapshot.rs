@@ -0,0 +1,147 @@
+use crate::db::Database;
+use crate::deb::{self, PackagesIndex};
+use crate::errors::*;
+use crate::models::snapshot::Snapshot;
+use rebuilderd_common::PkgGroup;
+use std::collections::HashSet;
+use std::io::prelude::*;
+
+/// The Packages indexes of a crawl, stored together with the generated build groups once the
+/// crawl succeeded.
+///
+/// The indexes are already in the blob store of the database, a snapshot only refers to them.
+pub struct PendingSnapshot {
+    indexes: Vec<(String, String)>,
+}
+
+impl PendingSnapshot {
+    /// Remember the indexes, they're only stored once the crawl succeeded
+    pub fn record(indexes: &[PackagesIndex]) -> PendingSnapshot {
+        let indexes = indexes
+            .iter()
+            .map(|index| (index.url.clone(), index.sha256.clone()))
+            .collect();
+        PendingSnapshot { indexes }
+    }
+
+    /// Store the snapshot with the build groups generated from it, returns its id
+    pub fn store(self, db: &Database, groups: &[PkgGroup]) -> Result<i32> {
+        let mut writer = db.blobs().writer()?;
+        serde_json::to_writer(&mut writer, groups)?;
+        let blob = writer.commit()?;
+        db.add_snapshot(self.indexes, blob.sha256)
+    }
+}
+
+/// Open the Packages indexes of a snapshot, the newest one is used if no id is given
+pub fn open(db: &Database, id: Option<i32>) -> Result<(Snapshot, Vec<PackagesIndex>)> {
+    let snapshot = match (db.snapshot(id)?, id) {
+        (Some(snapshot), _) => snapshot,
+        (None, Some(id)) => bail!("Snapshot #{} doesn't exist", id),
+        (None, None) => bail!("No snapshot found, run `crawl` first"),
+    };
+
+    let indexes = db
+        .snapshot_indexes(snapshot.id)?
+        .into_iter()
+        .map(|index| PackagesIndex {
+            url: index.url,
+            sha256: index.blob,
+            cache: None,
+        })
+        .collect();
+    Ok((snapshot, indexes))
+}
+
+/// The build groups that were generated when the snapshot was crawled
+pub fn build_groups(db: &Database, snapshot: &Snapshot) -> Result<Vec<PkgGroup>> {
+    let blob = snapshot.build_groups.as_deref().with_context(|| {
+        anyhow!(
+            "Snapshot #{} has no build groups, it was created by an older version",
+            snapshot.id
+        )
+    })?;
+    let mut buf = Vec::new();
+    db.blobs().open(blob)?.read_to_end(&mut buf)?;
+    let groups = serde_json::from_slice(&buf)
+        .with_context(|| anyhow!("Failed to parse build groups of snapshot #{}", snapshot.id))?;
+    Ok(groups)
+}
+
+/// How many binary packages of a snapshot have a buildinfo file
+#[derive(Debug, Default, PartialEq)]
+pub struct Coverage {
+    /// All binary packages, `Architecture: all` packages are only counted once
+    pub packages: usize,
+    /// The binary packages with a buildinfo file in the database
+    pub with_buildinfo: usize,
+}
+
+/// Look up the buildinfo file of every binary package in the indexes, without crawling or
+/// generating build groups
+pub fn coverage(db: &Database, indexes: &[PackagesIndex]) -> Result<Coverage> {
+    let mut coverage = Coverage::default();
+    let mut seen = HashSet::new();
+    for index in indexes {
+        let file = db.blobs().open(&index.sha256)?;
+        for pkg in deb::read_compressed_packages_db(file, &index.url)? {
+            let pkg =
+                pkg.with_context(|| anyhow!("Failed to parse package index: {:?}", index.url))?;
+            if !seen.insert((pkg.deb_folder.clone(), pkg.file_name.clone())) {
+                continue;
+            }
+            coverage.packages += 1;
+            let buildinfo = db.buildinfo_for_artifact(
+                &pkg.file_name,
+                pkg.sha256.as_deref(),
+                &pkg.buildinfo_version(),
+            )?;
+            if buildinfo.is_some() {
+                coverage.with_buildinfo += 1;
+            }
+        }
+    }
+    Ok(coverage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_and_open_snapshot() {
+        let db = Database::open(":memory:").unwrap();
+        let blob = db.blobs().add(b"Package: sniffglue\n").unwrap();
+        let indexes = vec![PackagesIndex {
+            url: "Packages".to_string(),
+            sha256: blob.sha256,
+            cache: None,
+        }];
+        let groups = vec![PkgGroup::new(
+            "sniffglue".to_string(),
+            "0.14.0-2".to_string(),
+            "debian".to_string(),
+            "main".to_string(),
+            "amd64".to_string(),
+            None,
+        )];
+        let id = PendingSnapshot::record(&indexes)
+            .store(&db, &groups)
+            .unwrap();
+
+        let (snapshot, indexes) = open(&db, None).unwrap();
+        assert_eq!(snapshot.id, id);
+        assert_eq!(indexes.len(), 1);
+        assert_eq!(indexes[0].url, "Packages");
+        let mut body = String::new();
+        db.blobs()
+            .open(&indexes[0].sha256)
+            .unwrap()
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "Package: sniffglue\n");
+        assert_eq!(build_groups(&db, &snapshot).unwrap(), groups);
+
+        assert!(open(&db, Some(id + 1)).is_err());
+    }
+}
@@ -1,36 +1,292 @@
 use crate::errors::*;
-use reqwest::Client;
+use rand::Rng;
+use reqwest::header::{
+    HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER,
+};
+use reqwest::{Client, Response, StatusCode};
 use std::fs;
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+use tokio::time::{self, Instant};
 
-pub async fn fetch_http(client: &Client, url: &str) -> Result<Vec<u8>> {
+/// Http client that retries failed requests and limits the number of requests per second
+pub struct HttpClient {
+    client: Client,
+    retries: u32,
+    backoff: Duration,
+    max_delay: Duration,
+    rate_limit: Option<RateLimiter>,
+}
+
+struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    async fn wait(&self) {
+        let mut next = self.next.lock().await;
+        let now = Instant::now();
+        if *next > now {
+            time::sleep_until(*next).await;
+        }
+        *next = (*next).max(now) + self.interval;
+    }
+}
+
+impl HttpClient {
+    /// A client that doesn't retry and has no rate limit
+    pub fn new(client: Client) -> HttpClient {
+        HttpClient {
+            client,
+            retries: 0,
+            backoff: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5 * 60),
+            rate_limit: None,
+        }
+    }
+
+    /// Retry failed requests this often, the delay starts with `backoff` and doubles after every attempt
+    pub fn retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    /// Never wait longer than this before retrying, even if the server asks for it
+    pub fn max_retry_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Limit the number of requests per second, 0 disables the limit
+    pub fn rate_limit(mut self, requests_per_second: f64) -> Self {
+        self.rate_limit = if requests_per_second > 0.0 {
+            Some(RateLimiter {
+                interval: Duration::from_secs_f64(1.0 / requests_per_second),
+                next: Mutex::new(Instant::now()),
+            })
+        } else {
+            None
+        };
+        self
+    }
+
+    fn retry_delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let delay = retry_after.unwrap_or_else(|| {
+            self.backoff
+                .checked_mul(2u32.saturating_pow(attempt))
+                .unwrap_or(Duration::MAX)
+        });
+        let jitter = rand::thread_rng().gen_range(0..=self.backoff.as_millis() as u64);
+        delay
+            .saturating_add(Duration::from_millis(jitter))
+            .min(self.max_delay)
+    }
+
+    /// Send a GET request and read the response with `read`.
+    ///
+    /// Failed requests are retried, including the ones where the connection broke while `read`
+    /// was reading the body. Each attempt calls `read` with a new response.
+    pub async fn get<T, F, Fut>(&self, url: &str, read: F) -> Result<T>
+    where
+        F: FnMut(Response) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        self.request(url, HeaderMap::new(), read)
+            .await?
+            .ok_or_else(|| anyhow!("Unexpected http status: {}", StatusCode::NOT_MODIFIED))
+    }
+
+    /// Send a conditional request and read the response with `read`, retried like [`get`].
+    ///
+    /// `None` is returned if the server responded with 304, `read` isn't called in that case.
+    ///
+    /// [`get`]: HttpClient::get
+    pub async fn get_conditional<T, F, Fut>(
+        &self,
+        url: &str,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+        read: F,
+    ) -> Result<Option<T>>
+    where
+        F: FnMut(Response) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = etag.and_then(|v| HeaderValue::from_str(v).ok()) {
+            headers.insert(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = last_modified.and_then(|v| HeaderValue::from_str(v).ok()) {
+            headers.insert(IF_MODIFIED_SINCE, last_modified);
+        }
+        self.request(url, headers, read).await
+    }
+
+    async fn request<T, F, Fut>(
+        &self,
+        url: &str,
+        headers: HeaderMap,
+        mut read: F,
+    ) -> Result<Option<T>>
+    where
+        F: FnMut(Response) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            if let Some(rate_limit) = &self.rate_limit {
+                rate_limit.wait().await;
+            }
+
+            let request = self.client.get(url).headers(headers.clone());
+            let (err, retry_after) = match request.send().await {
+                Ok(response) => {
+                    let status = response.status();
+                    debug!("Received http response: {}", status);
+                    if status == StatusCode::NOT_MODIFIED {
+                        debug!("Content of {:?} has not been modified", url);
+                        return Ok(None);
+                    }
+                    if status.is_success() {
+                        match read(response).await {
+                            Ok(value) => return Ok(Some(value)),
+                            // only errors of the connection are retried, not the ones of `read`
+                            Err(err) if is_http_error(&err) => (err, None),
+                            Err(err) => return Err(err),
+                        }
+                    } else {
+                        let retry_after = parse_retry_after(&response);
+                        let err = response
+                            .error_for_status()
+                            .err()
+                            .map(Error::from)
+                            .unwrap_or_else(|| anyhow!("Unexpected http status: {}", status));
+                        if !is_retryable(status) {
+                            return Err(err.context("Server responded with error"));
+                        }
+                        (err.context("Server responded with error"), retry_after)
+                    }
+                }
+                Err(err) => (Error::from(err).context("Failed to send request"), None),
+            };
+
+            if attempt >= self.retries {
+                return Err(err);
+            }
+
+            let delay = self.retry_delay(attempt, retry_after);
+            warn!(
+                "Request to {:?} failed, retrying in {:?}: {:#}",
+                url, delay, err
+            );
+            time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Get the `ETag` and `Last-Modified` headers, used for conditional requests later on
+pub fn cache_validators(response: &Response) -> (Option<String>, Option<String>) {
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+    };
+    (header(ETAG), header(LAST_MODIFIED))
+}
+
+fn is_http_error(err: &Error) -> bool {
+    err.chain().any(|err| err.is::<reqwest::Error>())
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+}
+
+fn parse_retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after_value(value, SystemTime::now())
+}
+
+fn parse_retry_after_value(value: &str, now: SystemTime) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        Some(Duration::from_secs(secs))
+    } else {
+        let date = httpdate::parse_http_date(value.trim()).ok()?;
+        Some(date.duration_since(now).unwrap_or_default())
+    }
+}
+
+pub async fn fetch_http(client: &HttpClient, url: &str) -> Result<Vec<u8>> {
     info!("Downloading from {:?}", url);
-    let response = client
-        .get(url)
-        .send()
-        .await
-        .context("Failed to send request")?;
-
-    debug!("Received http response: {}", response.status());
-    let content = response
-        .error_for_status()
-        .context("Server responded with error")?
-        .bytes()
-        .await
-        .context("Failed to download content")?;
+    let content = client
+        .get(url, |response| async {
+            response.bytes().await.context("Failed to download content")
+        })
+        .await?;
 
     debug!("Downloaded {} bytes", content.len());
 
     Ok(content.to_vec())
 }
 
-pub async fn read_path_or_url(client: &Client, s: &str) -> Result<Vec<u8>> {
-    if s.starts_with('/') || s.starts_with('.') {
+pub fn is_local_path(s: &str) -> bool {
+    s.starts_with('/') || s.starts_with('.')
+}
+
+pub async fn read_path_or_url(client: &HttpClient, s: &str) -> Result<Vec<u8>> {
+    if is_local_path(s) {
         info!("Reading from disk {:?}", s);
         let content =
-            fs::read(&s).with_context(|| anyhow!("Failed to read file from disk: {:?}", s))?;
+            fs::read(s).with_context(|| anyhow!("Failed to read file from disk: {:?}", s))?;
         debug!("Read {} bytes", content.len());
         Ok(content)
     } else {
         fetch_http(client, s).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_after_seconds() {
+        let d = parse_retry_after_value("120", SystemTime::now());
+        assert_eq!(d, Some(Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn test_retry_after_date() {
+        let now = httpdate::parse_http_date("Sat, 29 Jan 2022 08:13:42 GMT").unwrap();
+        let d = parse_retry_after_value("Sat, 29 Jan 2022 08:14:12 GMT", now);
+        assert_eq!(d, Some(Duration::from_secs(30)));
+        let d = parse_retry_after_value("Sat, 29 Jan 2022 08:00:00 GMT", now);
+        assert_eq!(d, Some(Duration::ZERO));
+        assert_eq!(parse_retry_after_value("soon", now), None);
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let client = HttpClient::new(Client::new()).retries(5, Duration::from_secs(1));
+        for attempt in 0..4 {
+            let delay = client.retry_delay(attempt, None);
+            let base = Duration::from_secs(1 << attempt);
+            assert!(delay >= base && delay <= base + Duration::from_secs(1));
+        }
+        let delay = client.retry_delay(0, Some(Duration::from_secs(30)));
+        assert!(delay >= Duration::from_secs(30) && delay <= Duration::from_secs(31));
+
+        let max = Duration::from_secs(5 * 60);
+        assert_eq!(client.retry_delay(20, None), max);
+        assert_eq!(client.retry_delay(100, None), max);
+        assert_eq!(client.retry_delay(0, Some(Duration::from_secs(86400))), max);
+    }
+}
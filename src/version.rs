@@ -0,0 +1,283 @@
+use crate::errors::*;
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// A Debian package version, `[epoch:]upstream[-revision]`, compared the same way dpkg does
+#[derive(Debug, Clone)]
+pub struct Version {
+    /// The epoch, 0 if there's none
+    pub epoch: u32,
+    /// The upstream version
+    pub upstream: String,
+    /// The Debian revision, `None` for native packages
+    pub revision: Option<String>,
+}
+
+impl Version {
+    /// The version as it shows up in file names, which never contain the epoch
+    pub fn without_epoch(&self) -> Version {
+        Version {
+            epoch: 0,
+            ..self.clone()
+        }
+    }
+
+    // the binNMU suffix is appended to the revision, or the upstream version of native packages
+    fn last_part(&self) -> &str {
+        self.revision.as_deref().unwrap_or(&self.upstream)
+    }
+
+    /// The number of the binary-only rebuild, if the version ends with `+bN`
+    pub fn binnmu(&self) -> Option<u32> {
+        let (_, num) = self.last_part().rsplit_once("+b")?;
+        if num.starts_with(|c: char| c.is_ascii_digit()) {
+            num.parse().ok()
+        } else {
+            None
+        }
+    }
+
+    /// The version of the source package this was built from, without the `+bN` suffix
+    pub fn without_binnmu(&self) -> Version {
+        let mut version = self.clone();
+        if self.binnmu().is_some() {
+            let part = version.revision.as_mut().unwrap_or(&mut version.upstream);
+            if let Some(idx) = part.rfind("+b") {
+                part.truncate(idx);
+            }
+        }
+        version
+    }
+}
+
+/// Compare two version strings.
+///
+/// Invalid versions sort after all valid ones and are compared as strings among themselves,
+/// so this is a total order that can be used with `sort_by`.
+pub fn compare(a: &str, b: &str) -> Ordering {
+    match (a.parse::<Version>(), b.parse::<Version>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+impl FromStr for Version {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Version> {
+        let s = s.trim();
+        let (epoch, rest) = match s.split_once(':') {
+            Some((epoch, rest)) => {
+                let epoch = epoch
+                    .parse()
+                    .with_context(|| anyhow!("Invalid epoch in version: {:?}", s))?;
+                (epoch, rest)
+            }
+            None => (0, s),
+        };
+
+        let (upstream, revision) = match rest.rsplit_once('-') {
+            Some((upstream, revision)) => (upstream, Some(revision.to_string())),
+            None => (rest, None),
+        };
+
+        if !upstream.starts_with(|c: char| c.is_ascii_digit()) {
+            bail!("Version needs to start with a digit: {:?}", s);
+        }
+
+        Ok(Version {
+            epoch,
+            upstream: upstream.to_string(),
+            revision,
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.epoch > 0 {
+            write!(f, "{}:", self.epoch)?;
+        }
+        write!(f, "{}", self.upstream)?;
+        if let Some(revision) = &self.revision {
+            write!(f, "-{}", revision)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Version) -> Ordering {
+        self.epoch
+            .cmp(&other.epoch)
+            .then_with(|| verrevcmp(&self.upstream, &other.upstream))
+            .then_with(|| {
+                verrevcmp(
+                    self.revision.as_deref().unwrap_or(""),
+                    other.revision.as_deref().unwrap_or(""),
+                )
+            })
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Version) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// sort weight of a character in the non-digit part, `~` sorts before everything, even the end
+fn order(c: Option<u8>) -> i32 {
+    match c {
+        None => 0,
+        Some(c) if c.is_ascii_digit() => 0,
+        Some(c) if c.is_ascii_alphabetic() => c as i32,
+        Some(b'~') => -1,
+        Some(c) => c as i32 + 256,
+    }
+}
+
+// port of verrevcmp from dpkg
+fn verrevcmp(a: &str, b: &str) -> Ordering {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() || j < b.len() {
+        while (i < a.len() && !a[i].is_ascii_digit()) || (j < b.len() && !b[j].is_ascii_digit()) {
+            let ac = order(a.get(i).copied());
+            let bc = order(b.get(j).copied());
+            if ac != bc {
+                return ac.cmp(&bc);
+            }
+            i += 1;
+            j += 1;
+        }
+
+        while i < a.len() && a[i] == b'0' {
+            i += 1;
+        }
+        while j < b.len() && b[j] == b'0' {
+            j += 1;
+        }
+
+        let mut first_diff = Ordering::Equal;
+        while i < a.len() && a[i].is_ascii_digit() && j < b.len() && b[j].is_ascii_digit() {
+            if first_diff == Ordering::Equal {
+                first_diff = a[i].cmp(&b[j]);
+            }
+            i += 1;
+            j += 1;
+        }
+
+        if i < a.len() && a[i].is_ascii_digit() {
+            return Ordering::Greater;
+        }
+        if j < b.len() && b[j].is_ascii_digit() {
+            return Ordering::Less;
+        }
+        if first_diff != Ordering::Equal {
+            return first_diff;
+        }
+    }
+
+    Ordering::Equal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmp(a: &str, b: &str) -> Ordering {
+        let a = a.parse::<Version>().unwrap();
+        let b = b.parse::<Version>().unwrap();
+        a.cmp(&b)
+    }
+
+    #[test]
+    fn test_parse_version() {
+        let v = "1:2.30-1+deb11u1".parse::<Version>().unwrap();
+        assert_eq!(
+            v,
+            Version {
+                epoch: 1,
+                upstream: "2.30".to_string(),
+                revision: Some("1+deb11u1".to_string()),
+            }
+        );
+        assert_eq!(v.to_string(), "1:2.30-1+deb11u1");
+
+        let v = "0.14.0-2-3".parse::<Version>().unwrap();
+        assert_eq!(v.upstream, "0.14.0-2");
+        assert_eq!(v.revision.as_deref(), Some("3"));
+
+        let v = "20220106".parse::<Version>().unwrap();
+        assert_eq!(v.revision, None);
+
+        assert!("a:1.0".parse::<Version>().is_err());
+        assert!("abc-1".parse::<Version>().is_err());
+    }
+
+    #[test]
+    fn test_compare_versions() {
+        assert_eq!(cmp("1.0-1", "1.0-1"), Ordering::Equal);
+        assert_eq!(cmp("1.0", "1.0-0"), Ordering::Equal);
+        assert_eq!(cmp("1.0-1", "1.0-2"), Ordering::Less);
+        assert_eq!(cmp("1:0.1-1", "2.0-1"), Ordering::Greater);
+        assert_eq!(cmp("0.14.0-2", "0.9.1-1"), Ordering::Greater);
+        assert_eq!(cmp("1.0~rc1-1", "1.0-1"), Ordering::Less);
+        assert_eq!(cmp("1.0-1", "1.0-1+b1"), Ordering::Less);
+        assert_eq!(cmp("1.0-1+b2", "1.0-1+b10"), Ordering::Less);
+        assert_eq!(cmp("1.0a-1", "1.0-1"), Ordering::Greater);
+        assert_eq!(cmp("1.0+dfsg-1", "1.0a-1"), Ordering::Greater);
+        assert_eq!(cmp("1.001-1", "1.1-1"), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_binnmu() {
+        let v = "1.0.16-3+b1".parse::<Version>().unwrap();
+        assert_eq!(v.binnmu(), Some(1));
+        assert_eq!(v.without_binnmu().to_string(), "1.0.16-3");
+
+        let v = "1:2.4+b12".parse::<Version>().unwrap();
+        assert_eq!(v.binnmu(), Some(12));
+        assert_eq!(v.without_binnmu().to_string(), "1:2.4");
+
+        let v = "0.14.0-2".parse::<Version>().unwrap();
+        assert_eq!(v.binnmu(), None);
+        assert_eq!(v.without_binnmu().to_string(), "0.14.0-2");
+
+        let v = "1.2+bpo1-1".parse::<Version>().unwrap();
+        assert_eq!(v.binnmu(), None);
+    }
+
+    #[test]
+    fn test_compare_strings() {
+        assert_eq!(compare("0.9.1-1", "0.14.0-2"), Ordering::Less);
+        assert_eq!(compare("0", "0.14.0-2"), Ordering::Less);
+        assert_eq!(compare("invalid", "0.14.0-2"), Ordering::Greater);
+        // string comparison would put this before the valid version
+        assert_eq!(compare("0.14.0-2", "+invalid"), Ordering::Less);
+        assert_eq!(compare("+invalid", "invalid"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_sort_mixed_versions() {
+        let mut versions = vec!["invalid", "0.14.0-2", "+invalid", "0.9.1-1", "1:0.1", "a"];
+        versions.sort_by(|a, b| compare(a, b));
+        assert_eq!(
+            versions,
+            &["0.9.1-1", "0.14.0-2", "1:0.1", "+invalid", "a", "invalid"]
+        );
+    }
+}